@@ -1,14 +1,110 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use regex::Regex;
-use anyhow::{Result, Context};
+use anyhow::{bail, Result, Context};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::env;
+use std::cell::{Cell, RefCell};
+use std::sync::OnceLock;
 
 // Embed config.txt contents at compile time
 const EMBEDDED_CONFIG: &str = include_str!("../config.txt");
 
+// Files written by `batch`; skipped when scanning so re-running batch is idempotent
+const BATCH_OUTPUT_FILENAMES: &[&str] = &["zh_srt.srt", "en_srt.srt", "bil_srt.srt", "en_srt_ok.srt"];
+
+// Endings of the names `batch --naming lang-suffix` writes and of processed `_ok` files
+const BATCH_OUTPUT_SUFFIXES: &[&str] = &[".zh.srt", ".en.srt", ".en-zh.srt", "_ok.srt"];
+
+// Environment variable naming a config file, used when no -c is given
+const CONFIG_ENV_VAR: &str = "SRT_HANDLE_CONFIG";
+
+// Default speaker label, e.g. `JOHN:` or `DR. SMITH:` at the start of a cue
+const SPEAKER_LABEL_PATTERN: &str = r"^[A-Z][A-Z .]*:\s*";
+
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+
+/// Global flags read by the print helpers and file I/O. main installs them with
+/// `begin_run`; they are per thread, so tests can run side by side with their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RunSettings {
+    strict: bool,
+    force: bool,
+    log_level: LogLevel,
+    encoding: InputEncoding,
+    /// `None` means colors follow whether the stream is a terminal
+    color: Option<bool>,
+}
+
+impl RunSettings {
+    const DEFAULT: RunSettings = RunSettings { strict: false, force: false, log_level: LogLevel::Info, encoding: InputEncoding::Auto, color: None };
+    
+    fn from_args(args: &Args) -> Self {
+        let no_color = args.no_color || env::var_os("NO_COLOR").is_some();
+        let forced = env::var_os("CLICOLOR_FORCE").is_some_and(|v| !v.is_empty() && v != "0");
+        RunSettings {
+            strict: args.strict,
+            force: args.force,
+            log_level: LogLevel::from_flags(args.quiet, args.verbose),
+            encoding: args.encoding,
+            color: color_override(no_color, forced),
+        }
+    }
+}
+
+impl Default for RunSettings {
+    fn default() -> Self {
+        RunSettings::DEFAULT
+    }
+}
+
+thread_local! {
+    static RUN_SETTINGS: Cell<RunSettings> = const { Cell::new(RunSettings::DEFAULT) };
+    
+    // Every warning and error printed during the run, checked at exit under --strict
+    static REPORTED_PROBLEMS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn run_settings() -> RunSettings {
+    RUN_SETTINGS.with(Cell::get)
+}
+
+/// Installs the settings for a run and forgets problems reported by an earlier one
+fn begin_run(settings: RunSettings) {
+    RUN_SETTINGS.with(|current| current.set(settings));
+    REPORTED_PROBLEMS.with(|problems| problems.borrow_mut().clear());
+}
+
+/// Which messages a run prints, ordered like the `log` crate's level filter so `-q` and
+/// `-v`/`-vv` mean what they would as a RUST_LOG setting. Warnings and errors always print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LogLevel {
+    /// --quiet: only warnings, errors and the reports a command was asked for
+    Warn,
+    Info,
+    /// -v: parsed entry and rule counts
+    Debug,
+    /// -vv: every per-entry rule decision
+    Trace,
+}
+
+impl LogLevel {
+    fn from_flags(quiet: bool, verbose: u8) -> Self {
+        match (quiet, verbose) {
+            (true, _) => LogLevel::Warn,
+            (false, 0) => LogLevel::Info,
+            (false, 1) => LogLevel::Debug,
+            (false, _) => LogLevel::Trace,
+        }
+    }
+}
+
 #[derive(Parser)]
 #[command(name = "srt-handle")]
 #[command(about = "A CLI tool to process SRT subtitle files")]
@@ -19,6 +115,25 @@ COMMANDS:
   process       Process a single SRT file with configuration rules
   batch         Batch process SRT files in current directory with standardized naming
   merge         Merge bilingual SRT file with same timestamps into single entries
+  validate      Check an SRT file for structural problems
+  diff          Compare two SRT files entry by entry
+  from-frames   Convert a frame-numbered caption file to SRT
+  stats         Report entry count, time span and subtitle coverage
+  head          Print the first N entries of an SRT file
+  tail          Print the last N entries of an SRT file
+  export        Export entries to other formats (csv)
+  scan-languages Report the dominant language(s) of each SRT file in a directory
+  rebase        Shift all timestamps so the first entry starts at 00:00:00,000
+  interleave    Combine two monolingual SRT files into one bilingual file
+  resegment     Re-split the text into one cue per sentence
+  scale         Retime subtitles from one frame rate to another
+  shift         Move all timestamps earlier or later by a fixed offset
+  segment       Split a file into parts at long silences
+  doctor        Check the install and the embedded config
+  repair        Fix arrows, separators, line endings and numbering in place
+  init-config   Print an annotated starter config
+  import        Build an SRT file from a CSV spreadsheet export
+  split-by-speaker Write one SRT file per speaker
 
 EXAMPLES:
   # Process single file
@@ -30,12 +145,94 @@ EXAMPLES:
   # Merge bilingual SRT file
   srt-handle merge bilingual.srt
 
+  # Check a file for duplicate indices
+  srt-handle validate input.srt
+
+  # Review only the text changes between two versions
+  srt-handle diff old.srt new.srt --text-only
+
+  # Convert frame-numbered captions at 25fps
+  srt-handle from-frames captions.txt --fps 25 -o out.srt
+
+  # Show how much of the file's span has subtitles
+  srt-handle stats input.srt
+
+  # Inspect the first 20 entries of a large file
+  srt-handle head input.srt -n 20
+
+  # Export entries as CSV for spreadsheet review
+  srt-handle export input.srt --format csv -o entries.csv
+
+  # Audit the languages of every SRT file in a folder
+  srt-handle scan-languages /path/to/library --json
+
+  # Rebase a clip extracted mid-video so it starts at zero
+  srt-handle rebase clip.srt -o clip_zeroed.srt
+
+  # Combine separate English and Chinese files into a bilingual file
+  srt-handle interleave en.srt zh.srt -o bil.srt
+
+  # One cue per sentence, timed proportionally to sentence length
+  srt-handle resegment --by sentence lecture.srt -o lecture_sentences.srt
+
+  # Retime a 25fps PAL release to 23.976, reading the source rate from the file name
+  srt-handle scale Movie.25fps.srt --to-fps 23.976 --assume-fps
+
+  # Move every cue 2.5 seconds earlier
+  srt-handle shift movie.srt --by -2.5s
+
+  # Split a recording into chapters wherever nobody speaks for 30 seconds
+  srt-handle segment lecture.srt --min-gap 30s
+
+  # Confirm the binary and its embedded config are healthy
+  srt-handle doctor
+
+  # Fix the structure of a malformed file in place
+  srt-handle repair broken.srt
+
+  # Write a commented starter config to edit
+  srt-handle init-config > myconfig.txt
+
+  # Build an SRT file from a spreadsheet with start,end,text columns
+  srt-handle import lines.csv --format csv -o lines.srt
+
+  # One file per speaker for dubbing or voice-over work
+  srt-handle split-by-speaker script.srt --out-dir voices/
+
   # Process with custom config and output
   srt-handle process input.srt -c custom.txt -o output.srt
 ")]
 struct Args {
     #[command(subcommand)]
     command: Commands,
+    
+    #[arg(long, global = true, help = "Disable colored output")]
+    no_color: bool,
+    
+    #[arg(long, global = true, help = "Treat warnings as errors and exit non-zero if any occur")]
+    strict: bool,
+    
+    #[arg(long, global = true, help = "Overwrite output files that already exist")]
+    force: bool,
+    
+    #[arg(long, global = true, value_enum, default_value_t = InputEncoding::Auto, help = "Encoding of input SRT files")]
+    encoding: InputEncoding,
+    
+    #[arg(short, long, global = true, conflicts_with = "verbose", help = "Only print warnings, errors and the reports a command was asked for")]
+    quiet: bool,
+    
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, help = "Print more detail; -vv also logs every per-entry rule decision")]
+    verbose: u8,
+}
+
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum InputEncoding {
+    /// Detect from the byte order mark, or from null bytes for BOM-less UTF-16
+    #[default]
+    Auto,
+    Utf8,
+    Utf16le,
+    Utf16be,
 }
 
 #[derive(Subcommand)]
@@ -48,16 +245,22 @@ enum Commands {
         #[arg(short, long, help = "Output SRT file path")]
         output: Option<PathBuf>,
         
-        #[arg(short, long, help = "Configuration file path (uses embedded config if not specified)")]
+        #[arg(short, long, help = "Configuration file path (falls back to $SRT_HANDLE_CONFIG, then the embedded config)")]
         config: Option<PathBuf>,
+        
+        #[command(flatten)]
+        options: Box<ProcessOptions>,
     },
     /// Batch process SRT files in current directory with standardized naming
     Batch {
-        #[arg(short, long, default_value = ".", help = "Directory to process")]
+        #[arg(short, long, default_value = ".", help = "Directory to process, or a .zip archive of SRT files to extract and process")]
         dir: PathBuf,
         
-        #[arg(short, long, help = "Configuration file path (uses embedded config if not specified)")]
+        #[arg(short, long, help = "Configuration file path (falls back to $SRT_HANDLE_CONFIG, then the embedded config)")]
         config: Option<PathBuf>,
+        
+        #[command(flatten)]
+        options: BatchOptions,
     },
     /// Merge bilingual SRT file with same timestamps into single entries
     Merge {
@@ -66,383 +269,6743 @@ enum Commands {
         
         #[arg(short, long, help = "Output merged SRT file path")]
         output: Option<PathBuf>,
+        
+        #[arg(long, value_name = "N", default_value_t = 2, help = "Number of consecutive entries (languages) merged into one cue")]
+        lines: usize,
+        
+        #[arg(long, conflicts_with = "lines", help = "Input holds one language after another, separated by a `---` line; pair the sections by position")]
+        grouped: bool,
+        
+        #[command(flatten)]
+        options: MergeOptions,
+        
+        #[command(flatten)]
+        format: OutputFormat,
+    },
+    /// Check an SRT file for structural problems
+    Validate {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(long, value_name = "N", help = "Fail unless the file has at least N entries")]
+        expect_min_entries: Option<usize>,
+        
+        #[arg(long, value_parser = parse_decimal, value_name = "CPS", help = "Fail if any entry reads faster than CPS characters per second")]
+        expect_max_cps: Option<f64>,
+        
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration_ms, help = "Fail if any entry lasts longer than DURATION (e.g. 7s)")]
+        expect_max_cue: Option<u64>,
+    },
+    /// Compare two SRT files entry by entry
+    Diff {
+        #[arg(help = "Original SRT file path")]
+        old: PathBuf,
+        
+        #[arg(help = "Changed SRT file path")]
+        new: PathBuf,
+        
+        #[arg(long, help = "Report only text changes, ignoring timestamp differences")]
+        text_only: bool,
+        
+        #[arg(long, value_name = "MS", default_value_t = 0, help = TOLERANCE_HELP)]
+        timestamp_tolerance_ms: u64,
+    },
+    /// Convert a frame-numbered caption file (`start_frame end_frame text` per line) to SRT
+    FromFrames {
+        #[arg(help = "Input frame-numbered caption file path")]
+        input: PathBuf,
+        
+        #[arg(short, long, help = "Output SRT file path")]
+        output: Option<PathBuf>,
+        
+        #[arg(long, value_parser = parse_decimal, help = "Frame rate of the frame numbers (23.976, 29.97 and 59.94 use exact NTSC rates)")]
+        fps: f64,
+        
+        #[arg(long, help = "Frame numbers are drop-frame timecode counts (29.97/59.94 only)")]
+        drop_frame: bool,
+        
+        #[command(flatten)]
+        format: OutputFormat,
+    },
+    /// Report entry count, time span and subtitle coverage
+    Stats {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(long, value_parser = parse_decimal, help = "Also show durations in frames at this frame rate (23.976, 29.97 and 59.94 use exact NTSC rates)")]
+        fps: Option<f64>,
+        
+        #[arg(long, help = "List entries whose reading speed is more than two standard deviations above the file's mean")]
+        cps_outliers: bool,
+    },
+    /// Print the first N entries of an SRT file
+    Head {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(short = 'n', long, default_value_t = 10, help = "Number of entries to print")]
+        count: usize,
+    },
+    /// Print the last N entries of an SRT file
+    Tail {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(short = 'n', long, default_value_t = 10, help = "Number of entries to print")]
+        count: usize,
+    },
+    /// Export entries to another format for review in other tools
+    Export {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(short, long, help = "Output file path (prints to stdout if not specified)")]
+        output: Option<PathBuf>,
+        
+        #[arg(long, value_enum, help = "Export format")]
+        format: ExportFormat,
+    },
+    /// Report the dominant language(s) of each SRT file in a directory without changing anything
+    ScanLanguages {
+        #[arg(default_value = ".", help = "Directory to scan")]
+        dir: PathBuf,
+        
+        #[arg(long, help = "Print the report as JSON")]
+        json: bool,
+    },
+    /// Shift all timestamps so the first entry starts at 00:00:00,000
+    Rebase {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(short, long, help = "Output SRT file path (default: <input>_rebased.srt)")]
+        output: Option<PathBuf>,
+        
+        #[command(flatten)]
+        format: OutputFormat,
+    },
+    /// Combine two monolingual SRT files into one bilingual file
+    Interleave {
+        #[arg(help = "SRT file whose text goes first (e.g. English)")]
+        first: PathBuf,
+        
+        #[arg(help = "SRT file whose text goes second (e.g. Chinese)")]
+        second: PathBuf,
+        
+        #[arg(short, long, help = "Output bilingual SRT file path (default: <first>_interleaved.srt)")]
+        output: Option<PathBuf>,
+        
+        #[command(flatten)]
+        options: MergeOptions,
+        
+        #[command(flatten)]
+        format: OutputFormat,
+    },
+    /// Re-split the whole text into new cues (e.g. one per sentence)
+    Resegment {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(long, value_enum, default_value_t = ResegmentBy::Sentence, help = "Unit each output cue holds")]
+        by: ResegmentBy,
+        
+        #[arg(short, long, help = "Output SRT file path (default: <input>_resegmented.srt)")]
+        output: Option<PathBuf>,
+        
+        #[command(flatten)]
+        format: OutputFormat,
+    },
+    /// Retime subtitles made for one frame rate to another (e.g. PAL speed-up)
+    Scale {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(short, long, help = "Output SRT file path (default: <input>_scaled.srt)")]
+        output: Option<PathBuf>,
+        
+        #[arg(long, value_parser = parse_decimal, help = "Frame rate the subtitles were timed for (23.976, 29.97 and 59.94 use exact NTSC rates)")]
+        from_fps: Option<f64>,
+        
+        #[arg(long, value_parser = parse_decimal, help = "Frame rate to retime the subtitles for")]
+        to_fps: f64,
+        
+        #[arg(long, conflicts_with = "from_fps", help = "Infer --from-fps from a token in the file name such as 23.976fps or 25p")]
+        assume_fps: bool,
+        
+        #[command(flatten)]
+        format: OutputFormat,
+    },
+    /// Move all timestamps earlier or later by a fixed offset
+    Shift {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(long, value_name = "DURATION", allow_hyphen_values = true, value_parser = parse_signed_duration_ms, help = "Offset to add, negative to move earlier (e.g. -2.5s, 700ms)")]
+        by: i64,
+        
+        #[arg(short, long, help = "Output SRT file path (default: <input>_shifted.srt)")]
+        output: Option<PathBuf>,
+        
+        #[command(flatten)]
+        format: OutputFormat,
+    },
+    /// Split a file into separate files wherever the gap between cues exceeds a threshold
+    Segment {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration_ms, help = "Start a new segment after a gap longer than DURATION (e.g. 30s)")]
+        min_gap: u64,
+        
+        #[arg(long, value_name = "DIR", help = "Directory for the segment files (default: next to the input)")]
+        out_dir: Option<PathBuf>,
+        
+        #[command(flatten)]
+        format: OutputFormat,
+    },
+    /// Check that the binary and its embedded config work
+    Doctor,
+    /// Fix the structure of a malformed file (arrows, millisecond separators, line endings, BOM, numbering) without applying any rules
+    Repair {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(short, long, help = "Output SRT file path (default: repair the input in place)")]
+        output: Option<PathBuf>,
+        
+        #[command(flatten)]
+        format: OutputFormat,
+    },
+    /// Print an annotated starter config covering every directive
+    InitConfig,
+    /// Build an SRT file from a spreadsheet export
+    Import {
+        #[arg(help = "Input file path")]
+        input: PathBuf,
+        
+        #[arg(long, value_enum, default_value_t = ImportFormat::Csv, help = "Import format")]
+        format: ImportFormat,
+        
+        #[arg(short, long, help = "Output SRT file path (default: <input>.srt)")]
+        output: Option<PathBuf>,
+        
+        #[command(flatten)]
+        output_format: OutputFormat,
+    },
+    /// Write one SRT file per speaker, each holding only that speaker's cues
+    SplitBySpeaker {
+        #[arg(help = "Input SRT file path")]
+        input: PathBuf,
+        
+        #[arg(long, value_name = "DIR", help = "Directory for the speaker files (default: next to the input)")]
+        out_dir: Option<PathBuf>,
+        
+        #[arg(long, value_name = "REGEX", default_value = SPEAKER_LABEL_PATTERN, help = "Pattern of the speaker labels read from the start of cues without a metadata line")]
+        speaker_label_pattern: String,
+        
+        #[command(flatten)]
+        format: OutputFormat,
     },
 }
 
-#[derive(Debug, Clone)]
-struct SrtEntry {
-    index: u32,
-    timestamp: String,
-    text: String,
-}
-
-#[derive(Debug, Default)]
-struct Config {
-    skip_words: Vec<String>,
-    combine_phrases: Vec<(String, String)>,
-    end_words: Vec<String>,
-    insert_phrases: Vec<(String, String)>,
-    split_words: Vec<String>,
-}
-
-impl Config {
-    fn from_file(path: &PathBuf) -> Result<Self> {
-        let content = fs::read_to_string(path)
-            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
-        Self::from_content(&content)
-    }
+/// Options controlling how entries are rendered by `format_srt_output`
+#[derive(clap::Args, Debug, Default)]
+struct OutputFormat {
+    #[arg(long, default_value_t = 1, help = "Index assigned to the first output entry")]
+    renumber_start: u32,
     
-    fn from_embedded() -> Result<Self> {
-        Self::from_content(EMBEDDED_CONFIG)
-    }
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "no_renumber", help = "Increase the index by N from one entry to the next (e.g. 2 gives 1, 3, 5, ...)")]
+    renumber_step: u32,
     
-    fn from_content(content: &str) -> Result<Self> {
-        let mut config = Config::default();
-        
-        for line in content.lines() {
-            let line = line.trim();
-            if line.is_empty() {
-                continue;
-            }
-            
-            if let Some(skip_content) = line.strip_prefix("SKIP:") {
-                config.skip_words = parse_quoted_list(skip_content);
-            } else if let Some(combine_content) = line.strip_prefix("COMBINE:") {
-                config.combine_phrases = parse_combine_phrases(combine_content);
-            } else if let Some(end_content) = line.strip_prefix("END:") {
-                config.end_words = parse_quoted_list(end_content);
-            } else if let Some(insert_content) = line.strip_prefix("INSERT:") {
-                config.insert_phrases = parse_combine_phrases(insert_content);
-            } else if let Some(split_content) = line.strip_prefix("SPLIT:") {
-                config.split_words = parse_quoted_list(split_content);
-            }
+    #[arg(long, value_name = "WIDTH", help = "Zero-pad output indices to WIDTH digits (e.g. 4 gives 0001; nonstandard)")]
+    pad_indices: Option<usize>,
+    
+    #[arg(long, conflicts_with = "renumber_start", help = "Keep each entry's original index instead of renumbering")]
+    no_renumber: bool,
+    
+    #[arg(long, value_enum, default_value_t = TimestampFormat::Srt, help = "Timestamp style used in the output")]
+    timestamp_format: TimestampFormat,
+    
+    #[arg(long, help = "Write metadata lines found between index and timestamp (e.g. speaker names) back to the output")]
+    keep_index_comments: bool,
+    
+    #[arg(long, help = "Write `//` annotation lines back before their cues (nonstandard; players may reject them)")]
+    keep_annotations: bool,
+    
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration_ms, help = "Snap written timestamps to the nearest multiple of DURATION (e.g. 100ms)")]
+    round_to: Option<u64>,
+    
+    #[arg(long, help = "Keep trailing spaces at the end of output lines instead of trimming them")]
+    preserve_trailing_spaces: bool,
+}
+
+impl OutputFormat {
+    /// Index written for the entry at `position` in the output
+    fn output_index(&self, position: usize, entry: &SrtEntry) -> usize {
+        if self.no_renumber {
+            entry.index as usize
+        } else {
+            // A derived Default leaves the step at 0, which means 1
+            self.renumber_start as usize + position * self.renumber_step.max(1) as usize
         }
-        
-        Ok(config)
     }
 }
 
-fn parse_quoted_list(content: &str) -> Vec<String> {
-    let re = Regex::new(r#""([^"]+)""#).unwrap();
-    re.captures_iter(content)
-        .map(|cap| cap[1].to_string())
-        .collect()
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum TimestampFormat {
+    /// 00:01:02,345
+    #[default]
+    Srt,
+    /// 00:01:02.345
+    Vtt,
+    /// 0:01:02,345 (hours without leading zeros)
+    Compact,
 }
 
-fn parse_combine_phrases(content: &str) -> Vec<(String, String)> {
-    let phrases = parse_quoted_list(content);
-    let mut result = Vec::new();
+#[derive(clap::Args, Debug)]
+struct MergeOptions {
+    #[arg(long, default_value = "\\n", help = "Text placed between the two languages (\\n for a line break)")]
+    separator: String,
     
-    for phrase in phrases {
-        if let Some((first, second)) = phrase.split_once(' ') {
-            result.push((first.to_string(), second.to_string()));
-        }
-    }
+    #[arg(long, value_enum, default_value_t = PairBy::Timestamp, help = "How entries are paired for merging")]
+    pair_by: PairBy,
     
-    result
+    #[arg(long, value_name = "MS", default_value_t = 0, help = TOLERANCE_HELP)]
+    timestamp_tolerance_ms: u64,
+    
+    #[arg(long, help = "Keep only one line when merged entries have the same text (case-insensitive)")]
+    dedup_on_merge: bool,
 }
 
-fn parse_srt(content: &str) -> Result<Vec<SrtEntry>> {
-    let mut entries = Vec::new();
-    let blocks: Vec<&str> = content.split("\n\n").collect();
-    
-    for block in blocks {
-        let lines: Vec<&str> = block.lines().collect();
-        if lines.len() >= 3 {
-            if let Ok(index) = lines[0].parse::<u32>() {
-                let timestamp = lines[1].to_string();
-                let text = lines[2..].join(" ");
-                
-                entries.push(SrtEntry {
-                    index,
-                    timestamp,
-                    text,
-                });
-            }
+const TOLERANCE_HELP: &str = "Maximum start/end difference for timestamps to count as matching";
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum PairBy {
+    /// Pair entries that share the same timestamp
+    Timestamp,
+    /// Pair entries by position (merge: 1/2, 3/4, ...; interleave: nth with nth) using the first entry's timestamp
+    Index,
+}
+
+impl PairBy {
+    /// What an unmerged entry is missing, for the warning about it
+    fn partner(self) -> &'static str {
+        match self {
+            PairBy::Timestamp => "with the same timestamp",
+            PairBy::Index => "after it to pair by index",
         }
     }
     
-    Ok(entries)
+    /// Which entries make up a group, for the warning about a short one
+    fn group(self) -> &'static str {
+        match self {
+            PairBy::Timestamp => "with the same timestamp",
+            PairBy::Index => "left to group by index",
+        }
+    }
 }
 
-fn should_skip_entry(text: &str, skip_words: &[String]) -> bool {
-    let text_lower = text.to_lowercase();
-    skip_words.iter().any(|word| text_lower.contains(&word.to_lowercase()))
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum PunctuationStyle {
+    /// Full-width punctuation next to CJK text (，。！？：；（）)
+    Cjk,
+    /// Half-width ASCII punctuation (, . ! ? : ; ( ))
+    Ascii,
 }
 
-fn apply_combine_rules(entries: &mut Vec<SrtEntry>, combine_phrases: &[(String, String)]) {
-    let mut i = 0;
-    while i < entries.len().saturating_sub(1) {
-        let mut combined = false;
-        
-        for (first, second) in combine_phrases {
-            let current_text = &entries[i].text.to_lowercase();
-            let next_text = &entries[i + 1].text.to_lowercase();
-            
-            if current_text.ends_with(&first.to_lowercase()) && 
-               next_text.starts_with(&second.to_lowercase()) {
-                entries[i].text = format!("{} {}", entries[i].text, entries[i + 1].text);
-                entries.remove(i + 1);
-                combined = true;
-                break;
-            }
-        }
-        
-        if !combined {
-            i += 1;
-        }
-    }
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum LongCueSplit {
+    /// Divide the words evenly between the parts
+    #[default]
+    Words,
+    /// Repeat the full text in every part
+    Duplicate,
 }
 
-fn apply_end_rules(entries: &mut Vec<SrtEntry>, end_words: &[String]) {
-    let mut i = 0;
-    while i < entries.len().saturating_sub(1) {
-        let words: Vec<&str> = entries[i].text.split_whitespace().collect();
-        
-        for end_word in end_words {
-            let end_word_parts: Vec<&str> = end_word.split_whitespace().collect();
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum BatchNaming {
+    /// zh_srt.srt, en_srt.srt and bil_srt.srt
+    Fixed,
+    /// <video>.zh.srt, <video>.en.srt and <video>.en-zh.srt, from the original file name
+    LangSuffix,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum DuplicatePolicy {
+    /// Use the first file by name
+    First,
+    /// Stop without changing anything
+    Error,
+    /// Use the most recently modified file
+    Newest,
+    /// Use the largest file
+    Largest,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ResegmentBy {
+    /// One cue per sentence, ending at . ! ? or their full-width forms
+    Sentence,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum RuleKind {
+    /// SKIP words
+    Skip,
+    /// COMBINE pairs
+    Combine,
+    /// END words
+    End,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ImportFormat {
+    /// start,end,text columns (named in a header row), times as HH:MM:SS,mmm or seconds
+    Csv,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum CaseLocale {
+    /// Turkish
+    Tr,
+    /// Azerbaijani
+    Az,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    /// index,start,end,duration_ms,char_count,text
+    Csv,
+    /// WebVTT, with `//` annotations written as NOTE blocks
+    Vtt,
+    /// timestamp<TAB>text per line, for translation memory tools
+    Tsv,
+}
+
+#[derive(clap::Args, Debug)]
+struct BatchOptions {
+    #[arg(long, value_enum, default_value_t = BatchNaming::Fixed, help = "How the renamed files are named")]
+    naming: BatchNaming,
+    
+    #[arg(long, value_enum, default_value_t = DuplicatePolicy::First, help = "Which file to use when several share a language tag")]
+    on_duplicate: DuplicatePolicy,
+    
+    #[arg(short, long, help = "Delete the original files without asking for confirmation (required when stdin is not a terminal)")]
+    yes: bool,
+    
+    #[arg(long, help = "Print JSON progress events to stderr, one per line")]
+    progress_json: bool,
+    
+    #[arg(short, long, help = "Also process every subfolder; a failing folder is reported and the run continues")]
+    recursive: bool,
+    
+    #[arg(long, value_name = "TEMPLATE", help = "Name of the processed file, e.g. \"{stem}.{lang}.processed.srt\" (placeholders: {stem}, {ext}, {lang}, {index})")]
+    output_template: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Default)]
+struct ProcessOptions {
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "output", help = "Name the output from a template, e.g. \"{stem}.{lang}.processed.srt\" (placeholders: {stem}, {ext}, {lang}, {index})")]
+    output_template: Option<String>,
+    
+    #[command(flatten)]
+    format: OutputFormat,
+    
+    #[arg(long, help = "Only report entries the SKIP rules would remove, without writing output")]
+    check: bool,
+    
+    #[arg(long, help = "Re-parse the generated output and fail instead of writing it if it does not round-trip")]
+    verify_roundtrip: bool,
+    
+    #[arg(long, help = "Read and write block by block to bound memory on huge files (only for per-entry passes; other options fall back to in-memory)")]
+    stream: bool,
+    
+    #[arg(long, help = "Refuse to write the output if any cue breaks the --spec-* limits, listing the offenders")]
+    strict_srt: bool,
+    
+    #[arg(long, value_name = "DURATION", default_value = "7s", value_parser = parse_duration_ms, help = "Longest cue allowed by --strict-srt")]
+    spec_max_duration: u64,
+    
+    #[arg(long, value_name = "N", default_value_t = 84, help = "Most characters per cue allowed by --strict-srt, line breaks excluded")]
+    spec_max_chars: usize,
+    
+    #[arg(long, value_name = "N", default_value_t = 2, help = "Most lines per cue allowed by --strict-srt")]
+    spec_max_lines: usize,
+    
+    #[arg(long, help = "Print JSON progress events to stderr, one per line")]
+    progress_json: bool,
+    
+    #[arg(long, help = "List SKIP, COMBINE and END rules that never matched anything in this file")]
+    report_unused: bool,
+    
+    #[arg(long, help = "List output entries that read too fast, are shorter than --min-cue or have overlong lines, worst first")]
+    speed_report: bool,
+    
+    #[arg(long, value_name = "N", help = "Maximum number of following cues a cue may absorb through COMBINE rules")]
+    combine_limit: Option<usize>,
+    
+    #[arg(long, value_name = "N", default_value_t = 0, help = "Let the second half of a COMBINE pair start up to N cues further on, absorbing the cues in between")]
+    combine_lookahead: usize,
+    
+    #[arg(long, help = "When COMBINE joins cues mid-sentence, drop a trailing comma and lowercase the next fragment's first letter")]
+    combine_smart_punct: bool,
+    
+    #[arg(long, value_name = "N", help = "Skip a COMBINE join that would make the cue longer than N characters (CJK characters count as two)")]
+    combine_max_chars: Option<usize>,
+    
+    #[arg(long, help = "Re-run the COMBINE and END passes until they stop changing anything")]
+    iterate: bool,
+    
+    #[arg(long, value_name = "N", default_value_t = 10, help = "Most passes --iterate makes before giving up with a warning")]
+    max_iterations: usize,
+    
+    #[arg(long, help = "If the file looks like a same-timestamp bilingual file, merge the pairs first and skip COMBINE/END rules")]
+    detect_bilingual: bool,
+    
+    #[arg(long, value_name = "MS", default_value_t = 0, help = TOLERANCE_HELP)]
+    timestamp_tolerance_ms: u64,
+    
+    #[arg(long, value_name = "FILE", help = "Write a log of what each pass did to each original entry")]
+    audit: Option<PathBuf>,
+    
+    #[arg(long, help = "Print a unified diff of the changes, cue by cue, instead of writing the output")]
+    diff: bool,
+    
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "RULES", help = "Apply only these rule types from the config (e.g. --only skip)")]
+    only: Vec<RuleKind>,
+    
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "RULES", conflicts_with = "only", help = "Ignore these rule types from the config (e.g. --skip-rule combine,end)")]
+    skip_rule: Vec<RuleKind>,
+    
+    #[arg(long, help = "Split cues after END words instead of moving them to the next cue")]
+    end_as_split: bool,
+    
+    #[arg(long, conflicts_with = "end_as_split", help = "When an END rule moves words to the next cue, move the boundary between the two cues back in proportion to the text moved")]
+    redistribute_timing: bool,
+    
+    #[arg(long, help = "Let COMBINE and END rules tolerate small spelling differences (edit distance)")]
+    fuzzy: bool,
+    
+    #[arg(long, value_name = "N", default_value_t = 1, help = "Maximum edit distance accepted by --fuzzy (at most one edit per four characters of the rule)")]
+    fuzzy_distance: usize,
+    
+    #[arg(long, value_enum, help = "Use this language's casing rules when matching SKIP/COMBINE/END rules (e.g. Turkish dotted and dotless I)")]
+    locale: Option<CaseLocale>,
+    
+    #[arg(long, help = "Ignore styling tags such as <i>...</i> when matching SKIP/COMBINE/END rules (the tags are kept in the output)")]
+    match_ignore_tags: bool,
+    
+    #[arg(long, help = "Keep blank lines inside cues (stanza breaks); a cue only ends before an index and timestamp line")]
+    lyrics: bool,
+    
+    #[arg(long, help = "Repair UTF-8 text that was mis-decoded as Windows-1252/Latin-1 (e.g. 'æ—¥æœ¬' -> '日本')")]
+    fix_mojibake: bool,
+    
+    #[arg(long, help = "Join the lines of multi-line cues into a single line")]
+    flatten: bool,
+    
+    #[arg(long, help = "Remove a line that repeats the line right before it within the same cue")]
+    dedup_lines: bool,
+    
+    #[arg(long, help = "Remove speaker labels such as 'JOHN:' from the start of each cue")]
+    strip_speaker_labels: bool,
+    
+    #[arg(long, value_name = "REGEX", default_value = SPEAKER_LABEL_PATTERN, help = "Pattern of the labels removed by --strip-speaker-labels, matched at the start of the first line")]
+    speaker_label_pattern: String,
+    
+    #[arg(long, value_enum, value_name = "STYLE", help = "Convert punctuation to full-width (cjk) or half-width (ascii)")]
+    normalize_punctuation: Option<PunctuationStyle>,
+    
+    #[arg(long, help = "Keep only entries matching a SKIP word instead of removing them")]
+    invert_skip: bool,
+    
+    #[arg(long, help = "Apply SKIP words per line, removing only matching lines of a multi-line cue")]
+    skip_lines: bool,
+    
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration_ms, help = "Drop entries shorter than DURATION (e.g. 200ms)")]
+    skip_shorter_than: Option<u64>,
+    
+    #[arg(long, help = "Remove text at the start of a cue that repeats the end of the previous cue (rolling captions)")]
+    dedup_overlap: bool,
+    
+    #[arg(long, value_name = "N", default_value_t = 3, help = "Minimum number of repeated words treated as an overlap by --dedup-overlap")]
+    overlap_min_words: usize,
+    
+    #[arg(long, help = "Merge consecutive cues from the same speaker (metadata line) that are at most --max-speaker-gap apart")]
+    merge_same_speaker: bool,
+    
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = parse_duration_ms, help = "Largest gap between cues merged by --merge-same-speaker (e.g. 500ms)")]
+    max_speaker_gap: u64,
+    
+    #[arg(long, help = "Merge cues shorter than --min-cue into a neighbouring cue")]
+    join_short: bool,
+    
+    #[arg(long, value_name = "DURATION", default_value = "700ms", value_parser = parse_duration_ms, help = "Minimum cue duration used by --join-short and --autotime (e.g. 700ms, 1.5s)")]
+    min_cue: u64,
+    
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration_ms, help = "Split cues longer than DURATION into equal parts (e.g. 6s)")]
+    max_cue_duration: Option<u64>,
+    
+    #[arg(long, value_enum, default_value_t = LongCueSplit::Words, help = "How text is shared between the parts of a cue split by --max-cue-duration")]
+    long_cue_split: LongCueSplit,
+    
+    #[arg(long, help = "Extend cues that read faster than --target-cps (or last less than --min-cue) into the gap after them, stopping before the next cue")]
+    autotime: bool,
+    
+    #[arg(long, value_name = "CPS", default_value_t = 17.0, value_parser = parse_decimal, help = "Reading speed --autotime aims for, in characters per second")]
+    target_cps: f64,
+    
+    #[arg(long, value_name = "DURATION", default_value = "0ms", value_parser = parse_duration_ms, help = "Gap --autotime leaves before the next cue (e.g. 80ms)")]
+    autotime_gap: u64,
+    
+    #[arg(long, value_name = "N", help = "Truncate entries wider than N columns at a word boundary (CJK characters count as 2)")]
+    truncate_chars: Option<usize>,
+    
+    #[arg(long, default_value = "…", help = "Marker appended to truncated entries")]
+    ellipsis: String,
+}
+
+impl ProcessOptions {
+    fn combine_options(&self) -> CombineOptions {
+        CombineOptions {
+            limit: self.combine_limit,
+            lookahead: self.combine_lookahead,
+            smart_punct: self.combine_smart_punct,
+            max_chars: self.combine_max_chars,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SrtEntry {
+    index: u32,
+    timestamp: String,
+    text: String,
+    /// Leading ASS positioning tag such as `{\an8}`, held aside so text rules never see or duplicate it
+    position_tag: Option<String>,
+    /// Nonstandard metadata line between the index and timestamp, usually a speaker name
+    speaker: Option<String>,
+    /// `//` annotation lines found before or inside this cue, without the `//` marker
+    notes: Vec<String>,
+}
+
+/// A single SRT timestamp (`HH:MM:SS,mmm`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Timestamp {
+    hours: u32,
+    minutes: u32,
+    seconds: u32,
+    millis: u32,
+}
+
+/// Largest value representable with the two-digit SRT hour field (99:59:59,999)
+const MAX_TIMESTAMP_MILLIS: u64 = 99 * 3_600_000 + 59 * 60_000 + 59 * 1000 + 999;
+
+impl Timestamp {
+    fn parse(s: &str) -> Option<Self> {
+        let (hms, millis) = s.trim().split_once([',', '.'])?;
+        let mut parts = hms.split(':');
+        let hours = parts.next()?.trim().parse().ok()?;
+        let minutes = parts.next()?.parse().ok()?;
+        let seconds = parts.next()?.parse().ok()?;
+        let millis = millis.trim().parse().ok()?;
+        
+        if parts.next().is_some() || hours > 99 || minutes >= 60 || seconds >= 60 || millis >= 1000 {
+            return None;
+        }
+        
+        Some(Timestamp { hours, minutes, seconds, millis })
+    }
+    
+    fn to_millis(self) -> u64 {
+        self.hours as u64 * 3_600_000
+            + self.minutes as u64 * 60_000
+            + self.seconds as u64 * 1000
+            + self.millis as u64
+    }
+    
+    fn format(self, style: TimestampFormat) -> String {
+        match style {
+            TimestampFormat::Srt => self.to_string(),
+            TimestampFormat::Vtt => format!("{:02}:{:02}:{:02}.{:03}", self.hours, self.minutes, self.seconds, self.millis),
+            TimestampFormat::Compact => format!("{}:{:02}:{:02},{:03}", self.hours, self.minutes, self.seconds, self.millis),
+        }
+    }
+    
+    /// Values beyond 99:59:59,999 are capped so the output stays valid SRT. Where user input
+    /// can push a time past that (shift, scale, imports), use `checked_from_millis` instead.
+    fn from_millis(ms: u64) -> Self {
+        let ms = ms.min(MAX_TIMESTAMP_MILLIS);
+        Timestamp {
+            hours: (ms / 3_600_000) as u32,
+            minutes: (ms / 60_000 % 60) as u32,
+            seconds: (ms / 1000 % 60) as u32,
+            millis: (ms % 1000) as u32,
+        }
+    }
+    
+    /// Fails for times past 99:59:59,999 instead of capping them, since a wider hour
+    /// field (`100:00:00,000`) is not valid SRT
+    fn checked_from_millis(ms: u64) -> Result<Self> {
+        if ms > MAX_TIMESTAMP_MILLIS {
+            bail!("{}:{:02}:{:02},{:03} is past 99:59:59,999, the largest time SRT's two-digit hour field can hold",
+                ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000);
+        }
+        Ok(Timestamp::from_millis(ms))
+    }
+    
+    /// Moves the timestamp by `delta_ms`, clamping at zero
+    fn shifted(self, delta_ms: i64) -> Self {
+        Timestamp::from_millis(self.to_millis().saturating_add_signed(delta_ms))
+    }
+    
+    /// Snaps to the nearest multiple of `step_ms`, rounding halves up
+    fn rounded(self, step_ms: u64) -> Self {
+        let step_ms = step_ms.max(1);
+        Timestamp::from_millis((self.to_millis() + step_ms / 2) / step_ms * step_ms)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}:{:02},{:03}", self.hours, self.minutes, self.seconds, self.millis)
+    }
+}
+
+/// One `--audit` log line: what a pass did to the entry with original index `index`
+#[derive(Debug, Clone)]
+struct AuditRecord {
+    index: u32,
+    pass: &'static str,
+    detail: String,
+}
+
+/// The `start --> end` line of an SRT entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimeRange {
+    start: Timestamp,
+    end: Timestamp,
+}
+
+impl TimeRange {
+    fn parse(s: &str) -> Option<Self> {
+        let (start, end) = s.split_once("-->")?;
+        Some(TimeRange {
+            start: Timestamp::parse(start)?,
+            end: Timestamp::parse(end)?,
+        })
+    }
+    
+    fn duration_ms(&self) -> u64 {
+        self.end.to_millis().saturating_sub(self.start.to_millis())
+    }
+    
+    /// Whether both start and end are within `tolerance_ms` of `other`'s
+    fn approx_eq(&self, other: &TimeRange, tolerance_ms: u64) -> bool {
+        self.start.to_millis().abs_diff(other.start.to_millis()) <= tolerance_ms
+            && self.end.to_millis().abs_diff(other.end.to_millis()) <= tolerance_ms
+    }
+    
+    fn format(&self, style: TimestampFormat) -> String {
+        format!("{} --> {}", self.start.format(style), self.end.format(style))
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} --> {}", self.start, self.end)
+    }
+}
+
+/// Parses a plain number, accepting `,` as well as `.` as the decimal separator
+fn parse_decimal(s: &str) -> Result<f64, String> {
+    s.trim().replace(',', ".").parse()
+        .map_err(|_| format!("invalid number '{}'", s.trim()))
+}
+
+/// Parses a duration such as `700ms`, `1.5s` (or `1,5s`) or a bare number of milliseconds
+fn parse_duration_ms(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 1.0)
+    } else if let Some(secs) = s.strip_suffix('s') {
+        (secs, 1000.0)
+    } else {
+        (s, 1.0)
+    };
+    
+    let value = parse_decimal(number)
+        .map_err(|_| format!("invalid duration '{}', expected e.g. 700ms or 1.5s", s))?;
+    if value < 0.0 {
+        return Err(format!("duration '{}' must not be negative", s));
+    }
+    
+    Ok((value * scale).round() as u64)
+}
+
+/// Like `parse_duration_ms`, but allows a leading `-` or `+` (e.g. `-2.5s`)
+fn parse_signed_duration_ms(s: &str) -> Result<i64, String> {
+    let s = s.trim();
+    match s.strip_prefix('-') {
+        Some(rest) => parse_duration_ms(rest).map(|ms| -(ms as i64)),
+        None => parse_duration_ms(s.strip_prefix('+').unwrap_or(s)).map(|ms| ms as i64),
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Config {
+    skip_words: Vec<String>,
+    combine_phrases: Vec<(String, String)>,
+    combine_equal: Vec<String>,
+    end_words: Vec<String>,
+    insert_phrases: Vec<(String, String)>,
+    split_words: Vec<String>,
+}
+
+impl Config {
+    fn from_file(path: &PathBuf) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        
+        if path.extension().and_then(|s| s.to_str()) == Some("toml") {
+            Self::from_toml(&content)
+                .with_context(|| format!("Failed to parse TOML config file: {}", path.display()))
+        } else {
+            Self::from_content(&content)
+        }
+    }
+    
+    /// Parses a TOML config with `skip`, `end` and `split` string arrays and
+    /// `combine`/`insert` arrays of `[first, second]` pairs
+    fn from_toml(content: &str) -> Result<Self> {
+        let mut config = Config::default();
+        let mut parser = TomlParser::new(content);
+        
+        while let Some((key, value)) = parser.next_pair()? {
+            match key.as_str() {
+                "skip" => config.skip_words = toml_string_list(&key, value)?,
+                "combine" => config.combine_phrases = toml_pair_list(&key, value)?,
+                "combine_equal" => config.combine_equal = toml_string_list(&key, value)?,
+                "end" => config.end_words = toml_string_list(&key, value)?,
+                "insert" => config.insert_phrases = toml_pair_list(&key, value)?,
+                "split" => config.split_words = toml_string_list(&key, value)?,
+                _ => print_warning(&format!("Ignoring unknown config key '{}'", key)),
+            }
+        }
+        
+        Ok(config)
+    }
+    
+    fn from_embedded() -> Result<Self> {
+        Self::from_content(EMBEDDED_CONFIG)
+    }
+    
+    /// Resolves the config to use: explicit `-c` path, then `$SRT_HANDLE_CONFIG`, then the embedded config
+    fn resolve(config_path: &Option<PathBuf>) -> Result<Self> {
+        Self::resolve_with_env(config_path, env::var_os(CONFIG_ENV_VAR))
+    }
+    
+    /// `resolve` given the value of `$SRT_HANDLE_CONFIG`
+    fn resolve_with_env(config_path: &Option<PathBuf>, env_path: Option<std::ffi::OsString>) -> Result<Self> {
+        if let Some(path) = config_path {
+            return Self::from_file(path);
+        }
+        
+        match env_path {
+            Some(path) if !path.is_empty() => Self::from_file(&PathBuf::from(path)),
+            _ => Self::from_embedded(),
+        }
+    }
+    
+    fn from_content(content: &str) -> Result<Self> {
+        let mut config = Config::default();
+        
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            
+            if let Some(skip_content) = line.strip_prefix("SKIP:") {
+                config.skip_words = parse_quoted_list(skip_content);
+            } else if let Some(combine_content) = line.strip_prefix("COMBINE:") {
+                config.combine_phrases = parse_combine_phrases(combine_content);
+            } else if let Some(equal_content) = line.strip_prefix("COMBINE_EQUAL:") {
+                config.combine_equal = parse_quoted_list(equal_content);
+            } else if let Some(end_content) = line.strip_prefix("END:") {
+                config.end_words = parse_quoted_list(end_content);
+            } else if let Some(insert_content) = line.strip_prefix("INSERT:") {
+                config.insert_phrases = parse_combine_phrases(insert_content);
+            } else if let Some(split_content) = line.strip_prefix("SPLIT:") {
+                config.split_words = parse_quoted_list(split_content);
+            } else if !line.starts_with('#') {
+                print_warning(&format!("Ignoring unrecognized config line: {}", line));
+            }
+        }
+        
+        Ok(config)
+    }
+}
+
+fn parse_quoted_list(content: &str) -> Vec<String> {
+    let re = Regex::new(r#""([^"]+)""#).unwrap();
+    re.captures_iter(content)
+        .map(|cap| cap[1].to_string())
+        .collect()
+}
+
+/// Value types accepted in TOML configs: strings and (nested) arrays of them
+enum TomlValue {
+    String(String),
+    Array(Vec<TomlValue>),
+}
+
+/// Minimal parser for the TOML subset used by configs: `key = value` pairs whose
+/// values are basic/literal strings or arrays, with `#` comments.
+struct TomlParser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> TomlParser<'a> {
+    fn new(src: &'a str) -> Self {
+        TomlParser { src, pos: 0 }
+    }
+    
+    fn line(&self) -> usize {
+        self.src[..self.pos].matches('\n').count() + 1
+    }
+    
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+    
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+    
+    fn skip_whitespace(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {
+                    self.bump();
+                }
+                '\n' if newlines => {
+                    self.bump();
+                }
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+    
+    fn next_pair(&mut self) -> Result<Option<(String, TomlValue)>> {
+        self.skip_whitespace(true);
+        if self.peek().is_none() {
+            return Ok(None);
+        }
+        if self.peek() == Some('[') {
+            bail!("Line {}: tables are not supported, use top-level keys", self.line());
+        }
+        
+        let key_start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            self.bump();
+        }
+        let key = self.src[key_start..self.pos].to_string();
+        if key.is_empty() {
+            bail!("Line {}: expected a key", self.line());
+        }
+        
+        self.skip_whitespace(false);
+        if self.bump() != Some('=') {
+            bail!("Line {}: expected '=' after '{}'", self.line(), key);
+        }
+        self.skip_whitespace(false);
+        let value = self.parse_value()?;
+        
+        self.skip_whitespace(false);
+        match self.peek() {
+            None | Some('\n') => Ok(Some((key, value))),
+            Some(c) => bail!("Line {}: unexpected '{}' after value of '{}'", self.line(), c, key),
+        }
+    }
+    
+    fn parse_value(&mut self) -> Result<TomlValue> {
+        match self.peek() {
+            Some('"') => self.parse_basic_string(),
+            Some('\'') => self.parse_literal_string(),
+            Some('[') => self.parse_array(),
+            _ => bail!("Line {}: only strings and arrays are supported as values", self.line()),
+        }
+    }
+    
+    fn parse_basic_string(&mut self) -> Result<TomlValue> {
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(TomlValue::String(value)),
+                Some('\\') => match self.bump() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    _ => bail!("Line {}: unsupported escape sequence", self.line()),
+                },
+                Some('\n') | None => bail!("Line {}: unterminated string", self.line()),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+    
+    fn parse_literal_string(&mut self) -> Result<TomlValue> {
+        self.bump();
+        let start = self.pos;
+        loop {
+            match self.bump() {
+                Some('\'') => return Ok(TomlValue::String(self.src[start..self.pos - 1].to_string())),
+                Some('\n') | None => bail!("Line {}: unterminated string", self.line()),
+                Some(_) => {}
+            }
+        }
+    }
+    
+    fn parse_array(&mut self) -> Result<TomlValue> {
+        self.bump();
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace(true);
+            if self.peek() == Some(']') {
+                self.bump();
+                return Ok(TomlValue::Array(items));
+            }
+            items.push(self.parse_value()?);
+            self.skip_whitespace(true);
+            match self.bump() {
+                Some(',') => {}
+                Some(']') => return Ok(TomlValue::Array(items)),
+                _ => bail!("Line {}: expected ',' or ']' in array", self.line()),
+            }
+        }
+    }
+}
+
+fn toml_string_list(key: &str, value: TomlValue) -> Result<Vec<String>> {
+    let TomlValue::Array(items) = value else {
+        bail!("'{}' must be an array of strings", key);
+    };
+    items.into_iter()
+        .map(|item| match item {
+            TomlValue::String(s) => Ok(s),
+            TomlValue::Array(_) => bail!("'{}' must be an array of strings", key),
+        })
+        .collect()
+}
+
+fn toml_pair_list(key: &str, value: TomlValue) -> Result<Vec<(String, String)>> {
+    let TomlValue::Array(items) = value else {
+        bail!("'{}' must be an array of [first, second] pairs", key);
+    };
+    items.into_iter()
+        .map(|item| {
+            let pair = toml_string_list(key, item)?;
+            match <[String; 2]>::try_from(pair) {
+                Ok([first, second]) => Ok((first, second)),
+                Err(_) => bail!("'{}' must be an array of [first, second] pairs", key),
+            }
+        })
+        .collect()
+}
+
+fn parse_combine_phrases(content: &str) -> Vec<(String, String)> {
+    let phrases = parse_quoted_list(content);
+    let mut result = Vec::new();
+    
+    for phrase in phrases {
+        if let Some((first, second)) = phrase.split_once(' ') {
+            result.push((first.to_string(), second.to_string()));
+        }
+    }
+    
+    result
+}
+
+/// Drops header or metadata lines before the first index/timestamp line pair
+fn strip_preamble(content: &str) -> &str {
+    let content = content.trim_start_matches('\u{feff}');
+    let mut offset = 0;
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    
+    for (i, line) in lines.iter().enumerate() {
+        if starts_block(&lines[i..]) {
+            let preamble = &content[..offset];
+            // Annotations before the first cue belong to it
+            if preamble.lines().all(|l| l.trim().is_empty() || is_annotation(l)) {
+                return content;
+            }
+            if !preamble.trim().is_empty() {
+                let skipped = preamble.lines().filter(|l| !l.trim().is_empty()).count();
+                print_warning(&format!("Skipped {} preamble line(s) before the first entry", skipped));
+            }
+            return &content[offset..];
+        }
+        offset += line.len();
+    }
+    
+    content
+}
+
+/// Whether `lines` begin with the index and timestamp lines (in either order) of an entry,
+/// allowing one metadata line between index and timestamp
+fn starts_block(lines: &[&str]) -> bool {
+    let line = lines.first().copied().unwrap_or("");
+    let next = lines.get(1).copied().unwrap_or("");
+    let after = lines.get(2).copied().unwrap_or("");
+    
+    if line.trim().parse::<u32>().is_ok() {
+        next.contains("-->") || (!next.trim().is_empty() && after.contains("-->"))
+    } else {
+        line.contains("-->") && next.trim().parse::<u32>().is_ok()
+    }
+}
+
+fn parse_srt(content: &str) -> Result<Vec<SrtEntry>> {
+    parse_blocks(split_blocks(strip_preamble(content)))
+}
+
+/// Splits at empty lines, the way `--stream` reads blocks; `lines` also drops the `\r`
+/// of CRLF files
+fn split_blocks(content: &str) -> Vec<Vec<&str>> {
+    let mut blocks = vec![Vec::new()];
+    for line in content.lines() {
+        match blocks.last_mut() {
+            Some(block) if !line.is_empty() => block.push(line),
+            _ => blocks.push(Vec::new()),
+        }
+    }
+    blocks
+}
+
+/// Like `parse_srt`, but a blank line only ends a cue when an index and timestamp
+/// follow it, so blank lines inside lyric cues (stanza breaks) are kept in the text
+fn parse_srt_lyrics(content: &str) -> Result<Vec<SrtEntry>> {
+    let lines: Vec<&str> = strip_preamble(content).lines().collect();
+    let mut blocks = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() && (starts_block(&lines[i + 1..]) || i + 1 == lines.len()) {
+            while current.last().is_some_and(|l| l.trim().is_empty()) {
+                current.pop();
+            }
+            blocks.push(std::mem::take(&mut current));
+            continue;
+        }
+        current.push(line);
+    }
+    blocks.push(current);
+    
+    parse_blocks(blocks)
+}
+
+fn parse_blocks(blocks: Vec<Vec<&str>>) -> Result<Vec<SrtEntry>> {
+    let mut parser = BlockParser::default();
+    let mut entries: Vec<SrtEntry> = blocks.iter().filter_map(|lines| parser.push(lines)).collect();
+    entries.extend(parser.finish());
+    
+    Ok(entries)
+}
+
+/// Turns blank-line separated blocks into entries one block at a time, for both the
+/// in-memory parser and `--stream`. A later block can still add text or annotations to
+/// the last entry, so that entry is held back until the next one starts.
+#[derive(Default)]
+struct BlockParser {
+    held: Option<SrtEntry>,
+    pending_notes: Vec<String>,
+}
+
+impl BlockParser {
+    /// Parses one block. Returns the previous entry once a new one has started.
+    fn push(&mut self, lines: &[&str]) -> Option<SrtEntry> {
+        // Annotation lines ahead of a cue are held for it so rules never see them; a `//`
+        // line after the timestamp is ordinary cue text
+        let first = lines.iter().position(|line| !line.trim().is_empty() && !is_annotation(line)).unwrap_or(lines.len());
+        self.pending_notes.extend(lines[..first].iter().filter(|line| is_annotation(line)).map(|line| annotation_text(line)));
+        let lines = &lines[first..];
+        if lines.is_empty() {
+            return None;
+        }
+        
+        // Only an index line next to a `-->` line starts a cue; anything else after a blank
+        // line (e.g. the next item of a numbered list) is text that belongs to the previous cue
+        if !starts_block(lines) && let Some(previous) = self.held.as_mut() {
+            print_warning(&format!("Block starting with '{}' has no index and timestamp; keeping it as text of entry {}", lines[0].trim(), previous.index));
+            previous.text = format!("{}\n\n{}", previous.text, lines.join("\n"));
+            return None;
+        }
+        
+        if lines.len() < 3 {
+            print_warning(&format!("Skipping malformed block starting with '{}'", lines[0].trim()));
+            return None;
+        }
+        
+        // Some malformed files put the timestamp before the index
+        let (index_line, timestamp_line) = if !lines[1].contains("-->") && lines[0].contains("-->") {
+            print_warning(&format!("Timestamp and index lines are swapped in block {}, reading them in reverse order", lines[1].trim()));
+            (lines[1], lines[0])
+        } else {
+            (lines[0], lines[1])
+        };
+        
+        // Some variants put a metadata line (such as a speaker name) between index and timestamp
+        let (speaker, timestamp_line, text_start) = if !timestamp_line.contains("-->") && lines[2].contains("-->") {
+            (Some(lines[1].trim().to_string()), lines[2], 3)
+        } else {
+            (None, timestamp_line, 2)
+        };
+        
+        let Ok(index) = index_line.trim().parse::<u32>() else {
+            print_warning(&format!("Skipping malformed block starting with '{}'", lines[0].trim()));
+            return None;
+        };
+        let text = lines[text_start..].join("\n");
+        let (position_tag, text) = split_position_tag(&text);
+        
+        self.held.replace(SrtEntry {
+            index,
+            timestamp: timestamp_line.to_string(),
+            text: text.to_string(),
+            position_tag,
+            speaker,
+            notes: std::mem::take(&mut self.pending_notes),
+        })
+    }
+    
+    /// Returns the last entry. Annotations after it stay with it rather than being lost.
+    fn finish(mut self) -> Option<SrtEntry> {
+        let mut last = self.held.take()?;
+        last.notes.append(&mut self.pending_notes);
+        Some(last)
+    }
+}
+
+/// Lines starting with `//` are working annotations, not subtitle text
+fn is_annotation(line: &str) -> bool {
+    line.trim_start().starts_with("//")
+}
+
+fn annotation_text(line: &str) -> String {
+    line.trim_start().trim_start_matches("//").trim().to_string()
+}
+
+/// Splits a leading `{\anN}` positioning tag off the cue text
+fn split_position_tag(text: &str) -> (Option<String>, &str) {
+    let re = Regex::new(r"^\s*(\{\\an[1-9]\})\s*").unwrap();
+    match re.captures(text) {
+        Some(caps) => (Some(caps[1].to_string()), &text[caps[0].len()..]),
+        None => (None, text),
+    }
+}
+
+/// Decides how rule words and phrases are matched against cue text. Every method
+/// defaults to case-insensitive comparison; implement the ones that need different logic.
+trait Matcher {
+    /// Case folding used by every comparison
+    fn lowercase(&self, text: &str) -> String {
+        text.to_lowercase()
+    }
+    
+    /// Whether a SKIP word occurs in `text`
+    fn contains(&self, text: &str, word: &str) -> bool {
+        self.lowercase(text).contains(&self.lowercase(word))
+    }
+    
+    /// Whether `text` ends with the first half of a COMBINE pair
+    fn ends_with(&self, text: &str, phrase: &str) -> bool {
+        self.lowercase(text).ends_with(&self.lowercase(phrase))
+    }
+    
+    /// Whether `text` starts with the second half of a COMBINE pair
+    fn starts_with(&self, text: &str, phrase: &str) -> bool {
+        self.lowercase(text).starts_with(&self.lowercase(phrase))
+    }
+    
+    /// Whether a word of the cue matches a word of an END rule
+    fn word_matches(&self, word: &str, rule_word: &str) -> bool {
+        self.lowercase(word) == self.lowercase(rule_word)
+    }
+}
+
+/// Lowercases `text`, using the dotted/dotless I rules of Turkish and Azerbaijani when
+/// `locale` asks for them (`I` -> `ı`, `İ` -> `i`) and Unicode default casing otherwise
+fn locale_lowercase(text: &str, locale: Option<CaseLocale>) -> String {
+    match locale {
+        Some(CaseLocale::Tr | CaseLocale::Az) => text.chars()
+            .map(|c| match c {
+                'I' => "ı".to_string(),
+                'İ' => "i".to_string(),
+                c => c.to_lowercase().collect(),
+            })
+            .collect(),
+        None => text.to_lowercase(),
+    }
+}
+
+/// Case-insensitive substring, prefix/suffix and whole-word matching
+#[derive(Default)]
+struct DefaultMatcher {
+    locale: Option<CaseLocale>,
+}
+
+impl Matcher for DefaultMatcher {
+    fn lowercase(&self, text: &str) -> String {
+        locale_lowercase(text, self.locale)
+    }
+}
+
+/// Tolerates small transcription differences in COMBINE and END rules using
+/// edit distance. A rule of n characters allows at most one edit per four
+/// characters, capped at `max_distance`, so short words still match exactly.
+struct FuzzyMatcher {
+    max_distance: usize,
+    locale: Option<CaseLocale>,
+}
+
+impl FuzzyMatcher {
+    fn is_close(&self, text: &str, rule: &str) -> bool {
+        let text = self.lowercase(text);
+        let rule = self.lowercase(rule);
+        let allowed = self.max_distance.min(rule.chars().count() / 4);
+        levenshtein(&text, &rule) <= allowed
+    }
+}
+
+impl Matcher for FuzzyMatcher {
+    fn lowercase(&self, text: &str) -> String {
+        locale_lowercase(text, self.locale)
+    }
+    
+    fn ends_with(&self, text: &str, phrase: &str) -> bool {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let count = phrase.split_whitespace().count();
+        count <= words.len() && self.is_close(&words[words.len() - count..].join(" "), phrase)
+    }
+    
+    fn starts_with(&self, text: &str, phrase: &str) -> bool {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let count = phrase.split_whitespace().count();
+        count <= words.len() && self.is_close(&words[..count].join(" "), phrase)
+    }
+    
+    fn word_matches(&self, word: &str, rule_word: &str) -> bool {
+        self.is_close(word, rule_word)
+    }
+}
+
+/// Compares text with styling tags (`<i>`, `</font>`, `{\an8}`-style overrides) removed, so
+/// styled cues match the same rules as plain ones; the tags stay in the output
+struct TagIgnoringMatcher {
+    inner: Box<dyn Matcher>,
+}
+
+impl Matcher for TagIgnoringMatcher {
+    fn lowercase(&self, text: &str) -> String {
+        self.inner.lowercase(text)
+    }
+    
+    fn contains(&self, text: &str, word: &str) -> bool {
+        self.inner.contains(&strip_tags(text), word)
+    }
+    
+    fn ends_with(&self, text: &str, phrase: &str) -> bool {
+        self.inner.ends_with(&strip_tags(text), phrase)
+    }
+    
+    fn starts_with(&self, text: &str, phrase: &str) -> bool {
+        self.inner.starts_with(&strip_tags(text), phrase)
+    }
+    
+    fn word_matches(&self, word: &str, rule_word: &str) -> bool {
+        self.inner.word_matches(&strip_tags(word), rule_word)
+    }
+}
+
+fn strip_tags(text: &str) -> String {
+    static TAGS: OnceLock<Regex> = OnceLock::new();
+    TAGS.get_or_init(|| Regex::new(r"</?[A-Za-z][^<>]*>|\{\\[^{}]*\}").unwrap())
+        .replace_all(text, "")
+        .into_owned()
+}
+
+/// Number of single-character insertions, deletions or substitutions turning `a` into `b`
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    
+    previous[b.len()]
+}
+
+fn find_skip_word<'a>(text: &str, skip_words: &'a [String], matcher: &dyn Matcher) -> Option<&'a String> {
+    skip_words.iter().find(|word| matcher.contains(text, word))
+}
+
+/// Lists each entry a SKIP word matches (index, word, text) and how many that is, for --check
+fn format_skip_check(entries: &[SrtEntry], skip_words: &[String], matcher: &dyn Matcher) -> String {
+    let mut report = String::new();
+    let mut matched = 0;
+    for entry in entries {
+        if let Some(word) = find_skip_word(&entry.text, skip_words, matcher) {
+            report.push_str(&format!("{} [{}] {}\n", entry.index, word, entry.text));
+            matched += 1;
+        }
+    }
+    report.push_str(&format!("SKIP rules would remove {} of {} entries\n", matched, entries.len()));
+    report
+}
+
+/// `limit` caps how many following cues a single cue may absorb (`None` = unlimited).
+/// With `lookahead` > 0 the second half may start up to that many cues further on,
+/// in which case the cues in between are absorbed too. Returns how often each pair fired.
+fn apply_combine_rules(entries: &mut Vec<SrtEntry>, combine_phrases: &[(String, String)], options: &CombineOptions, matcher: &dyn Matcher, audit: &mut Vec<AuditRecord>) -> Vec<usize> {
+    let CombineOptions { limit, lookahead, smart_punct, max_chars } = *options;
+    let mut hits = vec![0; combine_phrases.len()];
+    let mut i = 0;
+    let mut absorbed = 0;
+    while i < entries.len().saturating_sub(1) {
+        let mut combined = false;
+        
+        'phrases: for (rule, (first, second)) in combine_phrases.iter().enumerate() {
+            // Pairs never match across a hard line break, e.g. between the two languages of a merged cue
+            let text = entries[i].text.lines().last().unwrap_or("");
+            let text = if smart_punct { text.trim_end().trim_end_matches([',', '，']) } else { text };
+            if !matcher.ends_with(text, first) {
+                continue;
+            }
+            
+            for distance in 1..=lookahead + 1 {
+                if i + distance >= entries.len() || limit.is_some_and(|limit| absorbed + distance > limit) {
+                    break;
+                }
+                
+                if matcher.starts_with(entries[i + distance].text.lines().next().unwrap_or(""), second) {
+                    if let Some(max_chars) = max_chars {
+                        let joined = entries[i + 1..=i + distance].iter()
+                            .fold(entries[i].text.clone(), |text, next| join_combined_text(&text, &next.text, smart_punct));
+                        if joined.lines().map(display_width).sum::<usize>() > max_chars {
+                            audit.push(AuditRecord { index: entries[i].index, pass: "combine", detail: format!("not combined with {}: longer than {} characters", entries[i + distance].index, max_chars) });
+                            break;
+                        }
+                    }
+                    for next in entries.drain(i + 1..=i + distance).collect::<Vec<_>>() {
+                        audit.push(AuditRecord { index: entries[i].index, pass: "combine", detail: format!("combined with {} (\"{}\" / \"{}\")", next.index, first, second) });
+                        audit.push(AuditRecord { index: next.index, pass: "combine", detail: format!("absorbed into {}", entries[i].index) });
+                        entries[i].text = join_combined_text(&entries[i].text, &next.text, smart_punct);
+                        entries[i].notes.extend(next.notes);
+                    }
+                    absorbed += distance;
+                    hits[rule] += 1;
+                    combined = true;
+                    break 'phrases;
+                }
+            }
+        }
+        
+        if !combined {
+            i += 1;
+            absorbed = 0;
+        }
+    }
+    
+    hits
+}
+
+/// Limits on how far COMBINE rules may join cues
+#[derive(Debug, Default, Clone, Copy)]
+struct CombineOptions {
+    limit: Option<usize>,
+    lookahead: usize,
+    smart_punct: bool,
+    max_chars: Option<usize>,
+}
+
+/// Collapses runs of consecutive cues whose whole text is the same COMBINE_EQUAL phrase
+/// (a stutter) into one cue spanning them all. Returns how often each phrase fired.
+fn apply_combine_equal(entries: &mut Vec<SrtEntry>, phrases: &[String], audit: &mut Vec<AuditRecord>) -> Vec<usize> {
+    let mut hits = vec![0; phrases.len()];
+    let normalize = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    let mut i = 0;
+    while i + 1 < entries.len() {
+        let text = normalize(&entries[i].text);
+        let Some(rule) = phrases.iter().position(|phrase| normalize(phrase) == text) else {
+            i += 1;
+            continue;
+        };
+        
+        if normalize(&entries[i + 1].text) != text {
+            i += 1;
+            continue;
+        }
+        
+        let next = entries.remove(i + 1);
+        if let (Some(first), Some(second)) = (TimeRange::parse(&entries[i].timestamp), TimeRange::parse(&next.timestamp)) {
+            entries[i].timestamp = TimeRange { start: first.start, end: first.end.max(second.end) }.to_string();
+        }
+        entries[i].notes.extend(next.notes);
+        audit.push(AuditRecord { index: entries[i].index, pass: "combine-equal", detail: format!("collapsed repeat {}", next.index) });
+        audit.push(AuditRecord { index: next.index, pass: "combine-equal", detail: format!("collapsed into {}", entries[i].index) });
+        hits[rule] += 1;
+    }
+    hits
+}
+
+/// Joins two combined fragments with a space. With `smart_punct`, a join that continues a
+/// sentence drops the first fragment's trailing comma and lowercases the second fragment's
+/// first letter (except for "I" and its contractions); joins after `.`, `!`, `?` and
+/// similar are left alone.
+fn join_combined_text(first: &str, second: &str, smart_punct: bool) -> String {
+    if !smart_punct {
+        return format!("{} {}", first, second);
+    }
+    
+    let first = first.trim_end();
+    if first.ends_with(['.', '!', '?', '…', ':', '。', '！', '？']) {
+        return format!("{} {}", first, second);
+    }
+    let first = first.strip_suffix([',', '，']).unwrap_or(first);
+    
+    let first_word = second.split_whitespace().next().unwrap_or("");
+    let keep_case = first_word == "I" || first_word.starts_with("I'") || first_word.starts_with("I’");
+    let mut chars = second.chars();
+    let second = match chars.next() {
+        Some(c) if c.is_uppercase() && !keep_case => c.to_lowercase().chain(chars).collect(),
+        _ => second.to_string(),
+    };
+    
+    format!("{} {}", first, second)
+}
+
+/// Returns how often each END word fired. With `redistribute_timing` a move also moves
+/// the boundary between the two cues (see `shift_end_boundary`). A cue whose every word
+/// moved is dropped; with `redistribute_timing` the next cue then starts where it did.
+fn apply_end_rules(entries: &mut Vec<SrtEntry>, end_words: &[String], end_as_split: bool, redistribute_timing: bool, matcher: &dyn Matcher, audit: &mut Vec<AuditRecord>) -> Vec<usize> {
+    if end_as_split {
+        return split_at_end_words(entries, end_words, matcher, audit);
+    }
+    
+    let mut hits = vec![0; end_words.len()];
+    let mut i = 0;
+    while i < entries.len().saturating_sub(1) {
+        // Only the last line can end on a rule phrase; a phrase never spans a hard line break
+        let words: Vec<&str> = entries[i].text.lines().last().unwrap_or("").split_whitespace().collect();
+        let mut emptied = false;
+        
+        for (rule, end_word) in end_words.iter().enumerate() {
+            let end_word_parts: Vec<&str> = end_word.split_whitespace().collect();
+            
+            if words.len() >= end_word_parts.len() {
+                let last_words = &words[words.len() - end_word_parts.len()..];
+                
+                if last_words.iter().zip(end_word_parts.iter())
+                    .all(|(a, b)| matcher.word_matches(a, b)) {
+                    
+                    // Move the words as written, which may differ from the rule under --fuzzy
+                    let moved = last_words.join(" ");
+                    let total_chars = entries[i].text.chars().filter(|c| !c.is_whitespace()).count();
+                    entries[i].text = strip_trailing_words(&entries[i].text, end_word_parts.len()).to_string();
+                    emptied = entries[i].text.trim().is_empty();
+                    
+                    entries[i + 1].text = format!("{} {}", moved, entries[i + 1].text);
+                    let mut detail = format!("moved \"{}\" to {}", moved, entries[i + 1].index);
+                    if redistribute_timing {
+                        let moved_chars = moved.chars().filter(|c| !c.is_whitespace()).count();
+                        if let Some(shift_ms) = shift_end_boundary(entries, i, moved_chars, total_chars) {
+                            detail.push_str(&format!(", boundary {}ms earlier", shift_ms));
+                        }
+                    }
+                    audit.push(AuditRecord { index: entries[i].index, pass: "end", detail });
+                    hits[rule] += 1;
+                    break;
+                }
+            }
+        }
+        
+        // An empty cue would be written as a block that does not parse back
+        if emptied {
+            let gone = entries.remove(i);
+            if redistribute_timing
+                && let (Some(gone_range), Some(mut next)) = (TimeRange::parse(&gone.timestamp), TimeRange::parse(&entries[i].timestamp))
+                && gone_range.start < next.start {
+                next.start = gone_range.start;
+                entries[i].timestamp = next.to_string();
+            }
+            entries[i].notes.splice(0..0, gone.notes);
+            audit.push(AuditRecord { index: gone.index, pass: "end", detail: format!("dropped, every word moved to {}", entries[i].index) });
+            // The next cue now sits at `i` and may end on a rule phrase itself
+            continue;
+        }
+        i += 1;
+    }
+    
+    hits
+}
+
+/// After END moved `moved_chars` of cue `i`'s `total_chars` to cue `i + 1`, moves cue `i`'s
+/// end and cue `i + 1`'s start back by the same share of cue `i`'s duration, so the gap
+/// between them is kept. Cue `i` keeps at least 1ms. Nothing changes when every word moved
+/// (`apply_end_rules` drops that cue) or either timestamp does not parse. Returns how far
+/// the boundary moved.
+fn shift_end_boundary(entries: &mut [SrtEntry], i: usize, moved_chars: usize, total_chars: usize) -> Option<u64> {
+    if moved_chars == 0 || moved_chars >= total_chars {
+        return None;
+    }
+    let mut first = TimeRange::parse(&entries[i].timestamp)?;
+    let mut second = TimeRange::parse(&entries[i + 1].timestamp)?;
+    
+    let duration = first.duration_ms();
+    let shift_ms = ((duration as f64 * moved_chars as f64 / total_chars as f64).round() as u64)
+        .min(duration.saturating_sub(1));
+    if shift_ms == 0 {
+        return None;
+    }
+    
+    first.end = first.end.shifted(-(shift_ms as i64));
+    second.start = second.start.shifted(-(shift_ms as i64));
+    entries[i].timestamp = first.to_string();
+    entries[i + 1].timestamp = second.to_string();
+    Some(shift_ms)
+}
+
+/// Removes the last `count` words while keeping the line breaks of the rest
+fn strip_trailing_words(text: &str, count: usize) -> &str {
+    let mut remaining = text.trim_end();
+    for _ in 0..count {
+        remaining = match remaining.rfind(char::is_whitespace) {
+            Some(pos) => remaining[..pos].trim_end(),
+            None => "",
+        };
+    }
+    remaining
+}
+
+/// Removes the first `count` words while keeping the line breaks of the rest
+fn strip_leading_words(text: &str, count: usize) -> &str {
+    let mut remaining = text.trim_start();
+    for _ in 0..count {
+        remaining = match remaining.find(char::is_whitespace) {
+            Some(pos) => remaining[pos..].trim_start(),
+            None => "",
+        };
+    }
+    remaining
+}
+
+/// Lowercased word without surrounding punctuation, for loose text comparison
+fn normalize_word(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()
+}
+
+/// Rolling captions repeat the tail of one cue at the head of the next. When the
+/// longest such repeat has at least `min_words` words it is removed from the later
+/// cue; a cue left empty is dropped.
+fn apply_overlap_dedup(entries: &mut Vec<SrtEntry>, min_words: usize) {
+    let min_words = min_words.max(1);
+    let mut i = 1;
+    while i < entries.len() {
+        let previous: Vec<String> = entries[i - 1].text.split_whitespace().map(normalize_word).collect();
+        let current: Vec<String> = entries[i].text.split_whitespace().map(normalize_word).collect();
+        
+        let longest = (min_words..=previous.len().min(current.len()))
+            .rev()
+            .find(|&k| previous[previous.len() - k..] == current[..k]);
+        
+        if let Some(overlap) = longest {
+            entries[i].text = strip_leading_words(&entries[i].text, overlap).to_string();
+            if entries[i].text.trim().is_empty() {
+                entries.remove(i);
+                continue;
+            }
+        }
+        i += 1;
+    }
+}
+
+/// Treats END words as sentence terminators: a cue is split right after an END word
+/// that is followed by more text, keeping the END word with the first part.
+fn split_at_end_words(entries: &mut Vec<SrtEntry>, end_words: &[String], matcher: &dyn Matcher, audit: &mut Vec<AuditRecord>) -> Vec<usize> {
+    let mut hits = vec![0; end_words.len()];
+    let mut i = 0;
+    while i < entries.len() {
+        if let Some((first_text, second_text, rule)) = split_text_after_end_word(&entries[i].text, end_words, matcher) {
+            hits[rule] += 1;
+            audit.push(AuditRecord { index: entries[i].index, pass: "end", detail: format!("split before \"{}\"", second_text) });
+            
+            let ratio = first_text.chars().count() as f64
+                / (first_text.chars().count() + second_text.chars().count()) as f64;
+            let (first_timestamp, second_timestamp) = match TimeRange::parse(&entries[i].timestamp) {
+                Some(range) => {
+                    let (first, second) = split_time_range(&range, ratio);
+                    (first.to_string(), second.to_string())
+                }
+                None => (entries[i].timestamp.clone(), entries[i].timestamp.clone()),
+            };
+            
+            entries[i].text = first_text;
+            entries[i].timestamp = first_timestamp;
+            
+            // The new cue has no index of its own (0); output is renumbered, and --no-renumber
+            // gives it the index of the cue it came from
+            let new_entry = SrtEntry {
+                index: 0,
+                timestamp: second_timestamp,
+                text: second_text,
+                position_tag: entries[i].position_tag.clone(),
+                speaker: entries[i].speaker.clone(),
+                notes: Vec::new(),
+            };
+            entries.insert(i + 1, new_entry);
+        }
+        i += 1;
+    }
+    
+    hits
+}
+
+/// Cuts `text` after the first END word that more text follows, returning both halves and
+/// the index of that END word. Line breaks on either side of the cut are kept, so a
+/// bilingual or two-line cue keeps its layout.
+fn split_text_after_end_word(text: &str, end_words: &[String], matcher: &dyn Matcher) -> Option<(String, String, usize)> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut line_ends = Vec::new();
+    for line in text.lines() {
+        line_ends.push(line_ends.last().copied().unwrap_or(0) + line.split_whitespace().count());
+    }
+    let (split_at, rule) = find_end_word_boundary(&words, &line_ends, end_words, matcher)?;
+    
+    let mut halves = split_at_words(text, &[split_at]).into_iter();
+    Some((halves.next()?, halves.next()?, rule))
+}
+
+/// Splits `text` before each of the (ascending) word positions in `cuts`, giving
+/// `cuts.len() + 1` parts. Words keep their lines, so a part that spans a line break
+/// keeps it.
+fn split_at_words(text: &str, cuts: &[usize]) -> Vec<String> {
+    let lines: Vec<Vec<&str>> = text.lines().map(|line| line.split_whitespace().collect()).collect();
+    let total: usize = lines.iter().map(Vec::len).sum();
+    let bounds: Vec<usize> = std::iter::once(0).chain(cuts.iter().copied()).chain(std::iter::once(total)).collect();
+    
+    bounds.windows(2)
+        .map(|part| {
+            let mut seen = 0;
+            let mut part_lines = Vec::new();
+            for line in &lines {
+                let from = part[0].saturating_sub(seen).min(line.len());
+                let to = part[1].saturating_sub(seen).min(line.len());
+                if from < to {
+                    part_lines.push(line[from..to].join(" "));
+                }
+                seen += line.len();
+            }
+            part_lines.join("\n")
+        })
+        .collect()
+}
+
+/// Returns the word position to split at and the index of the END word found there.
+/// `line_ends` holds the word position after each line; a rule phrase may not span one.
+fn find_end_word_boundary(words: &[&str], line_ends: &[usize], end_words: &[String], matcher: &dyn Matcher) -> Option<(usize, usize)> {
+    for pos in 0..words.len() {
+        for (rule, end_word) in end_words.iter().enumerate() {
+            let end_word_parts: Vec<&str> = end_word.split_whitespace().collect();
+            let boundary = pos + end_word_parts.len();
+            
+            // An END word already closing the cue needs no split
+            if end_word_parts.is_empty() || boundary >= words.len() {
+                continue;
+            }
+            if line_ends.iter().any(|&end| pos < end && end < boundary) {
+                continue;
+            }
+            
+            if words[pos..boundary].iter().zip(end_word_parts.iter())
+                .all(|(a, b)| matcher.word_matches(a, b)) {
+                return Some((boundary, rule));
+            }
+        }
+    }
+    None
+}
+
+/// Splits a range at `ratio` (0.0-1.0) of its duration
+fn split_time_range(range: &TimeRange, ratio: f64) -> (TimeRange, TimeRange) {
+    let start_ms = range.start.to_millis();
+    let split_ms = start_ms + (range.duration_ms() as f64 * ratio).round() as u64;
+    let split = Timestamp::from_millis(split_ms);
+    
+    (
+        TimeRange { start: range.start, end: split },
+        TimeRange { start: split, end: range.end },
+    )
+}
+
+/// Splits cues longer than `max_ms` into the fewest equal-length parts that fit. With
+/// `LongCueSplit::Words` the words are divided evenly between the parts, keeping their
+/// line breaks; a cue with fewer words than parts repeats its text instead.
+fn apply_max_cue_duration(entries: &mut Vec<SrtEntry>, max_ms: u64, strategy: LongCueSplit) {
+    let max_ms = max_ms.max(1);
+    let mut i = 0;
+    while i < entries.len() {
+        let Some(range) = TimeRange::parse(&entries[i].timestamp) else {
+            i += 1;
+            continue;
+        };
+        
+        let parts = range.duration_ms().div_ceil(max_ms) as usize;
+        if parts <= 1 {
+            i += 1;
+            continue;
+        }
+        
+        let words = entries[i].text.split_whitespace().count();
+        let texts: Vec<String> = if strategy == LongCueSplit::Words && words >= parts {
+            let cuts: Vec<usize> = (1..parts).map(|part| part * words / parts).collect();
+            split_at_words(&entries[i].text, &cuts)
+        } else {
+            vec![entries[i].text.clone(); parts]
+        };
+        
+        let start_ms = range.start.to_millis();
+        let boundary = |part: usize| Timestamp::from_millis(start_ms + range.duration_ms() * part as u64 / parts as u64);
+        
+        let original = entries.remove(i);
+        for (part, text) in texts.into_iter().enumerate() {
+            let timestamp = TimeRange { start: boundary(part), end: boundary(part + 1) };
+            entries.insert(i + part, SrtEntry {
+                timestamp: timestamp.to_string(),
+                text,
+                notes: if part == 0 { original.notes.clone() } else { Vec::new() },
+                ..original.clone()
+            });
+        }
+        i += parts;
+    }
+}
+
+/// Merges cues shorter than `min_ms` into the next cue, or into the previous
+/// one when the short cue is the last in the file.
+fn apply_join_short(entries: &mut Vec<SrtEntry>, min_ms: u64) {
+    let mut i = 0;
+    while i < entries.len() && entries.len() > 1 {
+        let Some(range) = TimeRange::parse(&entries[i].timestamp) else {
+            i += 1;
+            continue;
+        };
+        
+        if range.duration_ms() >= min_ms {
+            i += 1;
+            continue;
+        }
+        
+        let (target, source) = if i + 1 < entries.len() { (i, i + 1) } else { (i - 1, i) };
+        let Some(target_range) = TimeRange::parse(&entries[target].timestamp) else {
+            i += 1;
+            continue;
+        };
+        let Some(source_range) = TimeRange::parse(&entries[source].timestamp) else {
+            i += 1;
+            continue;
+        };
+        
+        let joined = TimeRange { start: target_range.start, end: source_range.end };
+        entries[target].text = join_cue_texts(&entries[target].text, &entries[source].text);
+        entries[target].timestamp = joined.to_string();
+        let source_entry = entries.remove(source);
+        entries[target].notes.extend(source_entry.notes);
+        
+        // Re-check the joined cue in case it is still too short
+        i = target;
+    }
+}
+
+/// Joins the texts of two cues merged into one: a space between single-line texts, a line
+/// break when either has several lines so each keeps its layout
+fn join_cue_texts(first: &str, second: &str) -> String {
+    let separator = if first.contains('\n') || second.contains('\n') { "\n" } else { " " };
+    format!("{}{}{}", first, separator, second)
+}
+
+/// Extends each cue's end so it lasts at least `min_ms` and reads no faster than
+/// `target_cps` (counted like `chars_per_second`), using only the gap before the next cue
+/// (minus `gap_ms`). Cues are never shortened or moved. Returns how many cues were
+/// extended and how many still read too fast.
+fn apply_autotime(entries: &mut [SrtEntry], target_cps: f64, min_ms: u64, gap_ms: u64, audit: &mut Vec<AuditRecord>) -> (usize, usize) {
+    let (mut extended, mut too_fast) = (0, 0);
+    for i in 0..entries.len() {
+        let Some(range) = TimeRange::parse(&entries[i].timestamp) else {
+            continue;
+        };
+        
+        let chars: usize = entries[i].text.lines().map(display_width).sum();
+        let reading_ms = (chars as f64 * 1000.0 / target_cps.max(0.1)).ceil() as u64;
+        let wanted = range.start.to_millis() + reading_ms.max(min_ms);
+        let limit = match entries.get(i + 1).and_then(|next| TimeRange::parse(&next.timestamp)) {
+            Some(next) => next.start.to_millis().saturating_sub(gap_ms),
+            None => MAX_TIMESTAMP_MILLIS,
+        };
+        
+        let end = wanted.min(limit);
+        if end > range.end.to_millis() {
+            let new_range = TimeRange { start: range.start, end: Timestamp::from_millis(end) };
+            audit.push(AuditRecord { index: entries[i].index, pass: "autotime", detail: format!("end {} -> {}", range.end, new_range.end) });
+            entries[i].timestamp = new_range.to_string();
+            extended += 1;
+        }
+        if end.max(range.end.to_millis()) < wanted {
+            too_fast += 1;
+        }
+    }
+    (extended, too_fast)
+}
+
+/// Merges consecutive cues with the same speaker metadata when the gap between them is at most `max_gap_ms`
+fn apply_speaker_merge(entries: &mut Vec<SrtEntry>, max_gap_ms: u64) {
+    let mut i = 0;
+    while i + 1 < entries.len() {
+        let same_speaker = entries[i].speaker.is_some() && entries[i].speaker == entries[i + 1].speaker;
+        let ranges = TimeRange::parse(&entries[i].timestamp).zip(TimeRange::parse(&entries[i + 1].timestamp));
+        
+        let Some((current, next)) = ranges.filter(|_| same_speaker) else {
+            i += 1;
+            continue;
+        };
+        
+        if next.start.to_millis().saturating_sub(current.end.to_millis()) > max_gap_ms {
+            i += 1;
+            continue;
+        }
+        
+        let next_entry = entries.remove(i + 1);
+        entries[i].text = join_cue_texts(&entries[i].text, &next_entry.text);
+        entries[i].notes.extend(next_entry.notes);
+        entries[i].timestamp = TimeRange { start: current.start, end: next.end.max(current.end) }.to_string();
+    }
+}
+
+// Windows-1252 characters for bytes 0x80-0x9F; the five undefined bytes keep their C1 code point
+const CP1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+fn cp1252_byte(c: char) -> Option<u8> {
+    match c as u32 {
+        0x00..=0xFF => Some(c as u32 as u8),
+        _ => CP1252_HIGH.iter().position(|&high| high == c).map(|p| 0x80 + p as u8),
+    }
+}
+
+/// Reverses UTF-8 that was decoded as Windows-1252/Latin-1 and re-encoded.
+/// Returns `None` unless the line maps back to bytes that form valid UTF-8.
+fn repair_mojibake_line(line: &str) -> Option<String> {
+    if line.is_ascii() {
+        return None;
+    }
+    
+    let bytes: Vec<u8> = line.chars().map(cp1252_byte).collect::<Option<_>>()?;
+    let repaired = String::from_utf8(bytes).ok()?;
+    (repaired != line).then_some(repaired)
+}
+
+/// Repairs mojibake line by line and returns how many entries changed
+fn apply_mojibake_repair(entries: &mut [SrtEntry]) -> usize {
+    let mut changed = 0;
+    for entry in entries.iter_mut() {
+        let mut repaired_any = false;
+        let lines: Vec<String> = entry.text.lines()
+            .map(|line| match repair_mojibake_line(line) {
+                Some(repaired) => {
+                    repaired_any = true;
+                    repaired
+                }
+                None => line.to_string(),
+            })
+            .collect();
+        
+        if repaired_any {
+            entry.text = lines.join("\n");
+            changed += 1;
+        }
+    }
+    changed
+}
+
+fn apply_truncation(entries: &mut [SrtEntry], max_chars: usize, ellipsis: &str) {
+    for entry in entries.iter_mut() {
+        entry.text = truncate_text(&entry.text, max_chars, ellipsis);
+    }
+}
+
+fn truncate_text(text: &str, max_chars: usize, ellipsis: &str) -> String {
+    if display_width(text) <= max_chars {
+        return text.to_string();
+    }
+    
+    // The ellipsis counts towards the limit so the result stays within max_chars
+    let budget = max_chars.saturating_sub(display_width(ellipsis));
+    let mut truncated = String::new();
+    
+    for word in text.split_whitespace() {
+        let separator = if truncated.is_empty() { 0 } else { 1 };
+        if display_width(&truncated) + separator + display_width(word) > budget {
+            break;
+        }
+        if separator == 1 {
+            truncated.push(' ');
+        }
+        truncated.push_str(word);
+    }
+    
+    // A single word longer than the limit (or unspaced CJK text) has no boundary to cut at
+    if truncated.is_empty() {
+        let mut width = 0;
+        for c in text.chars() {
+            width += char_width(c);
+            if width > budget {
+                break;
+            }
+            truncated.push(c);
+        }
+    }
+    
+    format!("{}{}", truncated, ellipsis)
+}
+
+/// Number of terminal columns `text` occupies; CJK and fullwidth characters take two
+fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        // Combining marks, zero-width spaces/joiners and variation selectors
+        0x0300..=0x036F | 0x200B..=0x200F | 0xFE00..=0xFE0F => 0,
+        // East Asian Wide and Fullwidth blocks
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x2FFFD
+        | 0x30000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// Removes a speaker label matched by `pattern` from the start of the first line. A match
+/// whose colon sits between two digits is a time of day ("10:30") and is left alone.
+fn strip_speaker_label(text: &str, pattern: &Regex) -> String {
+    match split_speaker_label(text, pattern) {
+        Some((_, rest)) => rest.to_string(),
+        None => text.to_string(),
+    }
+}
+
+/// The speaker label at the start of `text` (without its colon) and the text after it
+fn split_speaker_label<'a>(text: &'a str, pattern: &Regex) -> Option<(&'a str, &'a str)> {
+    let label = pattern.find(text).filter(|m| m.start() == 0)?;
+    
+    let matched = label.as_str().trim_end();
+    let before_colon = matched.strip_suffix(':').and_then(|rest| rest.chars().last());
+    let after = text[label.end()..].chars().next();
+    if before_colon.is_some_and(|c| c.is_ascii_digit()) && after.is_some_and(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    
+    Some((matched.trim_end_matches(':').trim(), &text[label.end()..]))
+}
+
+/// Full-width punctuation and its ASCII counterpart, converted in both directions
+const PUNCTUATION_PAIRS: &[(char, char)] = &[
+    ('，', ','), ('。', '.'), ('！', '!'), ('？', '?'), ('：', ':'), ('；', ';'), ('（', '('), ('）', ')'),
+];
+
+/// Full-width punctuation with no unambiguous full-width form for the reverse direction
+const ASCII_ONLY_PUNCTUATION: &[(char, char)] = &[
+    ('、', ','), ('【', '['), ('】', ']'), ('“', '"'), ('”', '"'), ('‘', '\''), ('’', '\''), ('～', '~'),
+];
+
+fn is_cjk_char(c: char) -> bool {
+    char_width(c) == 2
+}
+
+fn normalize_punctuation(text: &str, style: PunctuationStyle) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    
+    while i < chars.len() {
+        let c = chars[i];
+        match style {
+            PunctuationStyle::Ascii => {
+                let ascii = PUNCTUATION_PAIRS.iter()
+                    .chain(ASCII_ONLY_PUNCTUATION)
+                    .find(|(full, _)| *full == c)
+                    .map(|(_, ascii)| *ascii);
+                let Some(ascii) = ascii else {
+                    out.push(c);
+                    i += 1;
+                    continue;
+                };
+                
+                // Full-width marks carry their own spacing; keep Latin words from running together
+                if matches!(ascii, '(' | '[') && out.chars().last().is_some_and(|p| p.is_alphanumeric() && !is_cjk_char(p)) {
+                    out.push(' ');
+                }
+                out.push(ascii);
+                if matches!(ascii, ',' | '.' | '!' | '?' | ':' | ';' | ')' | ']')
+                    && chars.get(i + 1).is_some_and(|n| n.is_alphanumeric() && !is_cjk_char(*n))
+                {
+                    out.push(' ');
+                }
+                i += 1;
+            }
+            PunctuationStyle::Cjk => {
+                let full = PUNCTUATION_PAIRS.iter()
+                    .find(|(_, ascii)| *ascii == c)
+                    .map(|(full, _)| *full);
+                let prev = out.trim_end_matches(' ').chars().last();
+                let next = chars[i + 1..].iter().copied().find(|n| *n != ' ');
+                
+                // Only convert marks attached to CJK text, leaving "3.14", "..." and English alone
+                let convert = match (full, c) {
+                    (None, _) => false,
+                    (_, '(') => next.is_some_and(is_cjk_char),
+                    (_, '.') => prev.is_some_and(is_cjk_char) && next != Some('.'),
+                    _ => prev.is_some_and(is_cjk_char),
+                };
+                if !convert {
+                    out.push(c);
+                    i += 1;
+                    continue;
+                }
+                
+                // Full-width marks take up their own spacing, so surrounding spaces are dropped
+                out.truncate(out.trim_end_matches(' ').len());
+                out.extend(full);
+                i += 1;
+                while chars.get(i) == Some(&' ') {
+                    i += 1;
+                }
+            }
+        }
+    }
+    
+    out
+}
+
+fn format_srt_output(entries: &[SrtEntry], format: &OutputFormat) -> String {
+    entries.iter()
+        .enumerate()
+        .map(|(i, entry)| format_srt_entry(i, entry, format))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Renders the entry at output `position`, without the blank line that separates entries
+fn format_srt_entry(position: usize, entry: &SrtEntry, format: &OutputFormat) -> String {
+    // Timestamps that don't parse are passed through untouched
+    let timestamp = match TimeRange::parse(&entry.timestamp) {
+        Some(range) => match format.round_to {
+            Some(step) => TimeRange { start: range.start.rounded(step), end: range.end.rounded(step) }.format(format.timestamp_format),
+            None => range.format(format.timestamp_format),
+        },
+        None => entry.timestamp.clone(),
+    };
+    let position_tag = entry.position_tag.as_deref().unwrap_or("");
+    let speaker = match &entry.speaker {
+        Some(speaker) if format.keep_index_comments => format!("{}\n", speaker),
+        _ => String::new(),
+    };
+    let index = format!("{:0width$}", format.output_index(position, entry), width = format.pad_indices.unwrap_or(0));
+    let notes: String = match format.keep_annotations {
+        true => entry.notes.iter().map(|note| format!("// {}\n", note)).collect(),
+        false => String::new(),
+    };
+    let notes = if notes.is_empty() { notes } else { notes + "\n" };
+    let block = format!("{}{}\n{}{}\n{}{}\n", notes, index, speaker, timestamp, position_tag, entry.text);
+    if format.preserve_trailing_spaces {
+        return block;
+    }
+    block.lines().map(|line| format!("{}\n", line.trim_end())).collect()
+}
+
+/// Reads an input subtitle file, decoding UTF-16 (with or without BOM) according to `--encoding`
+fn read_input_file(path: &Path) -> Result<String> {
+    if path.is_dir() {
+        bail!("Input {} is a directory; did you mean `srt-handle batch -d {}`?", path.display(), path.display());
+    }
+    if !path.exists() {
+        bail!("Input file not found: {}", path.display());
+    }
+    
+    let bytes = fs::read(path)
+        .with_context(|| format!("Failed to read input file: {}", path.display()))?;
+    decode_input(&bytes, run_settings().encoding)
+        .with_context(|| format!("Failed to decode input file: {}", path.display()))
+}
+
+/// Fails if `path` exists and --force was not given
+fn check_output_path(path: &Path) -> Result<()> {
+    if path.exists() && !run_settings().force {
+        bail!("Output file {} already exists; use --force to overwrite it", path.display());
+    }
+    Ok(())
+}
+
+/// Writes an output file, refusing to replace an existing one unless --force was given
+fn write_output_file(path: &Path, content: impl AsRef<[u8]>) -> Result<()> {
+    check_output_path(path)?;
+    
+    fs::write(path, content)
+        .with_context(|| format!("Failed to write output file: {}", path.display()))
+}
+
+fn detect_encoding(bytes: &[u8]) -> InputEncoding {
+    match bytes {
+        [0xFF, 0xFE, ..] => return InputEncoding::Utf16le,
+        [0xFE, 0xFF, ..] => return InputEncoding::Utf16be,
+        [0xEF, 0xBB, 0xBF, ..] => return InputEncoding::Utf8,
+        _ => {}
+    }
+    
+    // Without a BOM, UTF-16 text that is mostly ASCII has a null in every other byte
+    let sample = &bytes[..bytes.len().min(4096) & !1];
+    let pairs = sample.len() / 2;
+    let even_nulls = sample.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd_nulls = sample.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+    
+    if pairs > 0 && odd_nulls * 10 >= pairs * 3 && even_nulls * 10 < pairs {
+        InputEncoding::Utf16le
+    } else if pairs > 0 && even_nulls * 10 >= pairs * 3 && odd_nulls * 10 < pairs {
+        InputEncoding::Utf16be
+    } else {
+        InputEncoding::Utf8
+    }
+}
+
+fn decode_input(bytes: &[u8], encoding: InputEncoding) -> Result<String> {
+    let encoding = match encoding {
+        InputEncoding::Auto => detect_encoding(bytes),
+        other => other,
+    };
+    
+    let from_bytes: fn([u8; 2]) -> u16 = match encoding {
+        InputEncoding::Utf16le => u16::from_le_bytes,
+        InputEncoding::Utf16be => u16::from_be_bytes,
+        _ => return String::from_utf8(bytes.to_vec()).context("Input is not valid UTF-8 (try --encoding)"),
+    };
+    
+    if !bytes.len().is_multiple_of(2) {
+        bail!("Input has an odd number of bytes, so it is not valid UTF-16");
+    }
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| from_bytes([pair[0], pair[1]])).collect();
+    
+    // The BOM decodes to U+FEFF, which strip_preamble removes
+    String::from_utf16(&units).context("Input is not valid UTF-16")
+}
+
+/// Writes a JSON progress event to stderr so it never mixes with regular output
+fn emit_progress(enabled: bool, stage: &str, step: &str, done: usize, total: usize) {
+    if enabled {
+        eprintln!("{}", progress_event(stage, step, done, total));
+    }
+}
+
+fn progress_event(stage: &str, step: &str, done: usize, total: usize) -> String {
+    format!("{{\"stage\":{},\"step\":{},\"done\":{},\"total\":{}}}", json_string(stage), json_string(step), done, total)
+}
+
+/// `--no-color`/`$NO_COLOR` win over `$CLICOLOR_FORCE`; with neither, colors follow the terminal
+fn color_override(disabled: bool, forced: bool) -> Option<bool> {
+    if disabled {
+        Some(false)
+    } else if forced {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+fn paint(text: &str, color: &str, is_terminal: bool) -> String {
+    if run_settings().color.unwrap_or(is_terminal) {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn print_warning(message: &str) {
+    record_problem(message);
+    eprintln!("{}", paint(&format!("Warning: {}", message), YELLOW, io::stderr().is_terminal()));
+}
+
+fn print_error(message: &str) {
+    record_problem(message);
+    eprintln!("{}", paint(message, RED, io::stderr().is_terminal()));
+}
+
+fn record_problem(message: &str) {
+    REPORTED_PROBLEMS.with(|problems| problems.borrow_mut().push(message.to_string()));
+}
+
+fn has_reported_problems() -> bool {
+    REPORTED_PROBLEMS.with(|problems| !problems.borrow().is_empty())
+}
+
+/// Under --strict, fails the run when any warning or error was reported
+fn check_strict() -> Result<()> {
+    if !run_settings().strict {
+        return Ok(());
+    }
+    
+    let problems = REPORTED_PROBLEMS.with(|problems| problems.borrow().clone());
+    if problems.is_empty() {
+        return Ok(());
+    }
+    
+    let list: Vec<String> = problems.iter().map(|p| format!("  - {}", p.trim_end())).collect();
+    bail!("{} warning(s) treated as errors under --strict:\n{}", problems.len(), list.join("\n"))
+}
+
+/// Whether this run's -q/-v setting lets messages at `level` through
+fn log_enabled(level: LogLevel) -> bool {
+    level <= run_settings().log_level
+}
+
+/// Routine progress and summary messages, silenced by --quiet
+fn print_info(message: &str) {
+    if log_enabled(LogLevel::Info) {
+        println!("{}", message);
+    }
+}
+
+fn print_success(message: &str) {
+    if log_enabled(LogLevel::Info) {
+        println!("{}", paint(message, GREEN, io::stdout().is_terminal()));
+    }
+}
+
+/// Debug (-v) and trace (-vv) detail, on stderr so it never mixes with output
+fn print_log(level: LogLevel, message: &str) {
+    if log_enabled(level) {
+        eprintln!("{}", message);
+    }
+}
+
+/// Warns that `input` gave no entries; `blank` tells an empty file from one with no valid entries
+fn warn_empty_input(input: &Path, blank: bool) {
+    let reason = if blank {
+        "is empty"
+    } else {
+        "contains no valid SRT entries"
+    };
+    print_warning(&format!("{} {}, no output written", input.display(), reason));
+}
+
+/// Merges up to `lines` consecutive entries (one per language) into a single multi-line entry
+fn merge_bilingual_srt(input: &Path, output: &Option<PathBuf>, lines: usize, grouped: bool, options: &MergeOptions, format: &OutputFormat) -> Result<()> {
+    if lines < 2 {
+        bail!("--lines must be at least 2, got {}", lines);
+    }
+    
+    print_info(&format!("Merging bilingual SRT file: {}", input.display()));
+    
+    let content = read_input_file(input)?;
+    
+    let sections = if grouped {
+        let sections = split_language_sections(&content);
+        if sections.len() < 2 {
+            bail!("--grouped expects language sections separated by a `---` line, but {} has none", input.display());
+        }
+        sections.into_iter().map(parse_srt).collect::<Result<Vec<_>>>()?
+    } else {
+        vec![parse_srt(&content)?]
+    };
+    let total: usize = sections.iter().map(Vec::len).sum();
+    
+    if total == 0 {
+        warn_empty_input(input, content.trim().is_empty());
+        return Ok(());
+    }
+    
+    let merged_entries = if grouped {
+        merge_sections_by_position(&sections, options)
+    } else {
+        merge_entry_runs(&sections[0], lines, options)
+    };
+    
+    let output_content = format_srt_output(&merged_entries, format);
+    
+    let output_path = output.clone().unwrap_or_else(|| {
+        let mut path = input.to_path_buf();
+        if let Some(stem) = path.file_stem() {
+            let new_name = format!("{}_merged.srt", stem.to_string_lossy());
+            path.set_file_name(new_name);
+        }
+        path
+    });
+    
+    write_output_file(&output_path, output_content)?;
+    
+    print_success(&format!("Merged bilingual SRT saved to: {}", output_path.display()));
+    print_info(&format!("Merged {} subtitle entries into {} entries", total, merged_entries.len()));
+    
+    Ok(())
+}
+
+/// Splits a grouped multilingual file at marker lines made of three or more `-` or `=`
+fn split_language_sections(content: &str) -> Vec<&str> {
+    let marker = Regex::new(r"(?m)^[ \t]*(?:-{3,}|={3,})[ \t]*\r?$").unwrap();
+    marker.split(content).collect()
+}
+
+/// Merges the nth entry of every section into one entry, using the timing of the first
+/// section that has one. Positions missing from some sections are merged from the rest.
+fn merge_sections_by_position(sections: &[Vec<SrtEntry>], options: &MergeOptions) -> Vec<SrtEntry> {
+    let longest = sections.iter().map(Vec::len).max().unwrap_or(0);
+    if sections.iter().any(|section| section.len() != longest) {
+        let counts: Vec<String> = sections.iter().map(|section| section.len().to_string()).collect();
+        print_warning(&format!("Sections have different entry counts ({}); unmatched entries are kept on their own", counts.join(", ")));
+    }
+    
+    (0..longest)
+        .map(|position| {
+            let group: Vec<&SrtEntry> = sections.iter().filter_map(|section| section.get(position)).collect();
+            merge_entry_group(&group, options)
+        })
+        .collect()
+}
+
+/// Groups up to `lines` consecutive entries (by matching timestamp or by position) and
+/// merges each group into one entry. Entries without a partner are kept as they are.
+fn merge_entry_runs(entries: &[SrtEntry], lines: usize, options: &MergeOptions) -> Vec<SrtEntry> {
+    let mut merged_entries = Vec::new();
+    let mut i = 0;
+    
+    while i < entries.len() {
+        let mut end = i + 1;
+        while end < entries.len() && end - i < lines && match options.pair_by {
+            PairBy::Timestamp => timestamps_match(&entries[end].timestamp, &entries[i].timestamp, options.timestamp_tolerance_ms),
+            PairBy::Index => true,
+        } {
+            end += 1;
+        }
+        
+        let group: Vec<&SrtEntry> = entries[i..end].iter().collect();
+        if group.len() == 1 {
+            // Single entry, keep as is
+            print_warning(&format!("Entry {} has no partner {}, keeping it unmerged", entries[i].index, options.pair_by.partner()));
+        } else if group.len() < lines {
+            print_warning(&format!("Entry {} has only {} of {} entries {}, merging those", entries[i].index, group.len(), lines, options.pair_by.group()));
+        }
+        merged_entries.push(merge_entry_group(&group, options));
+        i = end;
+    }
+    
+    merged_entries
+}
+
+const BILINGUAL_SAMPLE: usize = 50;
+
+/// Whether the file looks like a `merge` input: among the first `BILINGUAL_SAMPLE`
+/// entries, at least half sit in consecutive pairs with matching timestamps
+fn looks_bilingual(entries: &[SrtEntry], tolerance_ms: u64) -> bool {
+    let sample = &entries[..entries.len().min(BILINGUAL_SAMPLE)];
+    let mut paired = 0;
+    let mut i = 0;
+    while i + 1 < sample.len() {
+        if timestamps_match(&sample[i].timestamp, &sample[i + 1].timestamp, tolerance_ms) {
+            paired += 2;
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+    paired >= 4 && paired * 2 >= sample.len()
+}
+
+/// Joins entries into one multilingual entry, keeping the first entry's timing and metadata
+fn merge_entry_group(group: &[&SrtEntry], options: &MergeOptions) -> SrtEntry {
+    let mut texts: Vec<&str> = Vec::new();
+    for entry in group {
+        // Same-timestamp duplicates are not a language pair, so they collapse to one line
+        if options.dedup_on_merge && texts.iter().any(|text| text.trim().to_lowercase() == entry.text.trim().to_lowercase()) {
+            continue;
+        }
+        texts.push(&entry.text);
+    }
+    SrtEntry {
+        text: texts.join(&options.separator),
+        ..group[0].clone()
+    }
+}
+
+/// Output name for a batch source file: the fixed `zh_srt.srt`-style names, or
+/// `<video>.<lang>.srt` with the bracketed language tag removed from the source name
+fn batch_target_name(source: &Path, lang: &str, naming: BatchNaming) -> String {
+    match naming {
+        BatchNaming::Fixed => match lang {
+            "zh" => "zh_srt.srt".to_string(),
+            "en" => "en_srt.srt".to_string(),
+            _ => "bil_srt.srt".to_string(),
+        },
+        BatchNaming::LangSuffix => {
+            let stem = source.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            let video = stem.split(" [").next().unwrap_or(&stem).trim();
+            format!("{}.{}.srt", video, lang)
+        }
+    }
+}
+
+/// Expands an output name template. `{stem}` and `{ext}` come from `input`, `{lang}` is the
+/// language code and `{index}` the file's 1-based position in the run. A relative result
+/// is placed next to the input.
+fn expand_output_template(template: &str, input: &Path, lang: &str, index: usize) -> Result<PathBuf> {
+    let placeholder = Regex::new(r"\{([^{}]*)\}").unwrap();
+    let mut unknown = None;
+    let name = placeholder.replace_all(template, |caps: &regex::Captures| match &caps[1] {
+        "stem" => input.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+        "ext" => input.extension().unwrap_or_default().to_string_lossy().into_owned(),
+        "lang" => lang.to_string(),
+        "index" => index.to_string(),
+        other => {
+            unknown.get_or_insert_with(|| other.to_string());
+            String::new()
+        }
+    });
+    if let Some(other) = unknown {
+        bail!("Unknown placeholder {{{}}} in output template (use {{stem}}, {{ext}}, {{lang}} or {{index}})", other);
+    }
+    if name.trim().is_empty() {
+        bail!("Output template '{}' expands to an empty file name", template);
+    }
+    
+    Ok(input.parent().unwrap_or(Path::new("")).join(name.as_ref()))
+}
+
+/// Chooses which of several files with the same language tag batch uses
+fn pick_batch_source(files: &[PathBuf], tag: &str, policy: DuplicatePolicy) -> Result<Option<PathBuf>> {
+    if files.len() <= 1 {
+        return Ok(files.first().cloned());
+    }
+    
+    let (chosen, how) = match policy {
+        DuplicatePolicy::First => (files.first(), "the first by name"),
+        DuplicatePolicy::Error => {
+            let names: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+            bail!("Found {} files with '{}': {}", files.len(), tag, names.join(", "));
+        }
+        DuplicatePolicy::Newest => (
+            files.iter().max_by_key(|f| fs::metadata(f).and_then(|m| m.modified()).ok()),
+            "the most recently modified",
+        ),
+        DuplicatePolicy::Largest => (
+            files.iter().max_by_key(|f| fs::metadata(f).map(|m| m.len()).unwrap_or(0)),
+            "the largest",
+        ),
+    };
+    
+    if let Some(chosen) = chosen {
+        print_warning(&format!("Found {} files with '{}', using {}: {}", files.len(), tag, how, chosen.display()));
+    }
+    Ok(chosen.cloned())
+}
+
+/// What `batch` does in one folder, worked out before any file is touched
+#[derive(Debug, Default)]
+struct BatchPlan {
+    /// Tagged source file, the copy made of it and the language label for messages
+    copies: Vec<(PathBuf, PathBuf, &'static str)>,
+    /// The English copy and where its processed version is written
+    process: Option<(PathBuf, PathBuf)>,
+    /// Tagged source files deleted after processing; never a file this run writes
+    originals: Vec<PathBuf>,
+}
+
+/// Whether batch itself writes files named like this, so a re-run must not treat them as sources
+fn is_batch_output_name(filename: &str) -> bool {
+    BATCH_OUTPUT_FILENAMES.contains(&filename)
+        || BATCH_OUTPUT_SUFFIXES.iter().any(|suffix| filename.ends_with(suffix))
+}
+
+/// Scans `dir` and decides which files to copy, process and delete. Returns `None` when
+/// the folder holds no candidate `.srt` files.
+fn plan_batch_folder(dir: &Path, options: &BatchOptions, index: usize) -> Result<Option<BatchPlan>> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+    
+    let mut srt_files = Vec::new();
+    
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        
+        if path.extension().and_then(|s| s.to_str()) == Some("srt")
+            && let Some(filename) = path.file_name().and_then(|s| s.to_str())
+            && !is_batch_output_name(filename) {
+            srt_files.push((path.clone(), filename.to_string()));
+        }
+    }
+    
+    if srt_files.is_empty() {
+        print_info("No SRT files found in directory.");
+        return Ok(None);
+    }
+    
+    print_info(&format!("Found {} SRT files", srt_files.len()));
+    
+    // read_dir order differs between platforms, so "first" means first by name
+    srt_files.sort();
+    
+    let mut zh_files = Vec::new();
+    let mut en_files = Vec::new();
+    let mut bil_files = Vec::new();
+    
+    for (path, filename) in &srt_files {
+        if filename.contains("[Chinese (Simplified)]") {
+            zh_files.push(path.clone());
+        } else if filename.contains("[English - English-Chinese (Simplified)]") {
+            bil_files.push(path.clone());
+        } else if filename.contains("[English - English]") {
+            en_files.push(path.clone());
+        }
+    }
+    
+    let sources = [
+        (pick_batch_source(&zh_files, "[Chinese (Simplified)]", options.on_duplicate)?, "zh", "Chinese"),
+        (pick_batch_source(&en_files, "[English - English]", options.on_duplicate)?, "en", "English"),
+        (pick_batch_source(&bil_files, "[English - English-Chinese (Simplified)]", options.on_duplicate)?, "en-zh", "bilingual"),
+    ];
+    
+    let mut plan = BatchPlan::default();
+    for (source, lang, label) in sources {
+        let Some(source) = source else {
+            continue;
+        };
+        
+        let target = dir.join(batch_target_name(&source, lang, options.naming));
+        if lang == "en" {
+            let output = match &options.output_template {
+                Some(template) => expand_output_template(template, &target, "en", index)?,
+                None => dir.join(format!("{}_ok.srt", target.file_stem().unwrap_or_default().to_string_lossy())),
+            };
+            plan.process = Some((target.clone(), output));
+        }
+        plan.copies.push((source, target, label));
+    }
+    
+    // Only tagged files are originals, and an --output-template name may coincide with one
+    let written: Vec<&PathBuf> = plan.copies.iter().map(|(_, target, _)| target)
+        .chain(plan.process.iter().map(|(_, output)| output))
+        .collect();
+    plan.originals = [zh_files, en_files, bil_files].concat().into_iter()
+        .filter(|path| !written.contains(&path))
+        .collect();
+    plan.originals.sort();
+    
+    Ok(Some(plan))
+}
+
+fn file_name_of(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+/// CRC-32 (IEEE) checksum, as stored for each file in a zip archive
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Little-endian field of `len` bytes at `at`, failing instead of panicking on a truncated archive
+fn le_field(data: &[u8], at: usize, len: usize) -> Result<u32> {
+    let bytes = data.get(at..at + len).context("Archive is truncated")?;
+    Ok(bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u32))
+}
+
+/// Reads every file of a zip archive through its central directory, returning each
+/// archived path with its contents. Stored and deflated files are supported; anything
+/// else (encryption, other methods, zip64) is an error rather than a silent skip.
+fn read_zip_entries(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    // The end-of-central-directory record is the last thing in the file, before a comment
+    // of up to 64 KiB
+    let end = (0..=data.len().saturating_sub(22)).rev()
+        .take(22 + 0xFFFF)
+        .find(|&at| le_field(data, at, 4).ok() == Some(0x0605_4b50))
+        .context("No zip end-of-central-directory record found")?;
+    let count = le_field(data, end + 10, 2)?;
+    let mut at = le_field(data, end + 16, 4)? as usize;
+    
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        if le_field(data, at, 4)? != 0x0201_4b50 {
+            bail!("Corrupt central directory entry at offset {}", at);
+        }
+        let flags = le_field(data, at + 8, 2)?;
+        let method = le_field(data, at + 10, 2)?;
+        let crc = le_field(data, at + 16, 4)?;
+        let compressed_size = le_field(data, at + 20, 4)? as usize;
+        let size = le_field(data, at + 24, 4)? as usize;
+        let name_len = le_field(data, at + 28, 2)? as usize;
+        let extra_len = le_field(data, at + 30, 2)? as usize;
+        let comment_len = le_field(data, at + 32, 2)? as usize;
+        let local = le_field(data, at + 42, 4)? as usize;
+        let name = String::from_utf8_lossy(data.get(at + 46..at + 46 + name_len).context("Archive is truncated")?).into_owned();
+        at += 46 + name_len + extra_len + comment_len;
+        
+        if name.ends_with('/') {
+            continue;
+        }
+        if flags & 1 != 0 {
+            bail!("{} is encrypted", name);
+        }
+        if compressed_size == 0xFFFF_FFFF || size == 0xFFFF_FFFF {
+            bail!("{} needs zip64, which is not supported", name);
+        }
+        
+        // The local header repeats the name but may carry a different extra field
+        if le_field(data, local, 4)? != 0x0403_4b50 {
+            bail!("Corrupt local header for {}", name);
+        }
+        let start = local + 30 + le_field(data, local + 26, 2)? as usize + le_field(data, local + 28, 2)? as usize;
+        let stored = data.get(start..start + compressed_size).context("Archive is truncated")?;
+        let content = match method {
+            0 => stored.to_vec(),
+            8 => inflate(stored).with_context(|| format!("Failed to decompress {}", name))?,
+            other => bail!("{} uses compression method {}; only stored and deflated files are supported", name, other),
+        };
+        if content.len() != size || crc32(&content) != crc {
+            bail!("{} fails its checksum", name);
+        }
+        entries.push((name, content));
+    }
+    
+    Ok(entries)
+}
+
+/// Reads DEFLATE's bit stream, least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, count: u8) -> Result<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self.data.get(self.pos / 8).context("Compressed data ends early")?;
+            value |= ((byte >> (self.pos % 8)) as u32 & 1) << i;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+}
+
+/// Canonical Huffman code, stored as the number of codes of each length and the symbols
+/// in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16).filter(|&s| lengths[s as usize] > 0).collect();
+        symbols.sort_by_key(|&s| lengths[s as usize]);
+        Huffman { counts, symbols }
+    }
+    
+    fn decode(&self, bits: &mut BitReader) -> Result<u16> {
+        // Codes of each length follow the last code of the previous length, shifted left
+        let (mut code, mut first, mut index) = (0usize, 0usize, 0usize);
+        for &count in &self.counts[1..] {
+            code |= bits.read(1)? as usize;
+            let count = count as usize;
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("Invalid Huffman code")
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Decompresses raw DEFLATE data (RFC 1951), the compression zip archives use
+fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut bits = BitReader { data, pos: 0 };
+    let mut out = Vec::new();
+    
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => {
+                // Stored block: byte-aligned length, its complement, then the raw bytes
+                bits.pos = bits.pos.div_ceil(8) * 8;
+                let len = bits.read(16)? as usize;
+                if bits.read(16)? as usize != !len & 0xFFFF {
+                    bail!("Stored block length does not match its complement");
+                }
+                let start = bits.pos / 8;
+                out.extend_from_slice(data.get(start..start + len).context("Compressed data ends early")?);
+                bits.pos += len * 8;
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                inflate_block(&mut bits, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let literal_count = bits.read(5)? as usize + 257;
+                let distance_count = bits.read(5)? as usize + 1;
+                let code_count = bits.read(4)? as usize + 4;
+                
+                const CODE_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+                let mut code_lengths = [0u8; 19];
+                for &symbol in &CODE_ORDER[..code_count] {
+                    code_lengths[symbol] = bits.read(3)? as u8;
+                }
+                let codes = Huffman::new(&code_lengths);
+                
+                let mut lengths = Vec::with_capacity(literal_count + distance_count);
+                while lengths.len() < literal_count + distance_count {
+                    let (value, repeat) = match codes.decode(&mut bits)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => (*lengths.last().context("Repeat code with no previous length")?, 3 + bits.read(2)?),
+                        17 => (0, 3 + bits.read(3)?),
+                        _ => (0, 11 + bits.read(7)?),
+                    };
+                    lengths.extend(std::iter::repeat_n(value, repeat as usize));
+                }
+                if lengths.len() > literal_count + distance_count {
+                    bail!("Code lengths overrun the header counts");
+                }
+                
+                let (literals, distances) = lengths.split_at(literal_count);
+                inflate_block(&mut bits, &mut out, &Huffman::new(literals), &Huffman::new(distances))?;
+            }
+            _ => bail!("Invalid block type"),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Decodes one Huffman-coded block into `out`, up to its end-of-block symbol
+fn inflate_block(bits: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        
+        let i = symbol - 257;
+        if i >= LENGTH_BASE.len() {
+            bail!("Invalid length symbol {}", symbol);
+        }
+        let len = LENGTH_BASE[i] as usize + bits.read(LENGTH_EXTRA[i])? as usize;
+        let d = distances.decode(bits)? as usize;
+        if d >= DISTANCE_BASE.len() {
+            bail!("Invalid distance symbol {}", d);
+        }
+        let distance = DISTANCE_BASE[d] as usize + bits.read(DISTANCE_EXTRA[d])? as usize;
+        if distance > out.len() {
+            bail!("Back-reference reaches before the start of the data");
+        }
+        // Copied one byte at a time, since a match may overlap the bytes it produces
+        for _ in 0..len {
+            out.push(out[out.len() - distance]);
+        }
+    }
+}
+
+/// Extracts the `.srt` files of a zip archive, without their folder paths, into a
+/// directory named after the archive. Two archived files with the same name would
+/// overwrite each other, so that is an error. The returned directory is absolute, so it
+/// stays valid whatever directory later commands run in.
+fn extract_zip_archive(archive: &Path) -> Result<PathBuf> {
+    let mut target = std::path::absolute(archive)
+        .with_context(|| format!("Failed to resolve {}", archive.display()))?;
+    target.set_extension("");
+    if target.exists() && !run_settings().force {
+        bail!("{} already exists; use --force to extract {} into it", target.display(), archive.display());
+    }
+    
+    let data = fs::read(archive)
+        .with_context(|| format!("Failed to read archive: {}", archive.display()))?;
+    let entries = read_zip_entries(&data)
+        .with_context(|| format!("Failed to read zip archive: {}", archive.display()))?;
+    
+    // Archived path each file name came from, to report collisions
+    let mut sources: BTreeMap<String, String> = BTreeMap::new();
+    let mut files = Vec::new();
+    for (path, content) in entries {
+        let name = path.rsplit(['/', '\\']).next().unwrap_or_default().to_string();
+        if !name.to_lowercase().ends_with(".srt") {
+            continue;
+        }
+        if let Some(earlier) = sources.insert(name.clone(), path.clone()) {
+            bail!("{} holds both {} and {}, which would be extracted to the same file {}", archive.display(), earlier, path, name);
+        }
+        files.push((name, content));
+    }
+    
+    fs::create_dir_all(&target)
+        .with_context(|| format!("Failed to create directory: {}", target.display()))?;
+    for (name, content) in &files {
+        let path = target.join(name);
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write output file: {}", path.display()))?;
+    }
+    
+    print_info(&format!("Extracted {} .srt file(s) from {} into {}", files.len(), archive.display(), target.display()));
+    Ok(target)
+}
+
+fn batch_process_srt_files(dir: &Path, config_path: &Option<PathBuf>, options: &BatchOptions) -> Result<()> {
+    let extracted;
+    let dir = if dir.is_file() && dir.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip")) {
+        extracted = extract_zip_archive(dir)?;
+        &extracted
+    } else {
+        dir
+    };
+    
+    if !options.recursive {
+        return batch_process_folder(dir, config_path, options, 1);
+    }
+    
+    let mut folders = Vec::new();
+    collect_srt_folders(dir, &mut folders);
+    if folders.is_empty() {
+        print_info(&format!("No SRT files found under {}", dir.display()));
+        return Ok(());
+    }
+    
+    // One bad folder should not stop the others, so errors are collected rather than returned
+    let mut failed = Vec::new();
+    for (position, folder) in folders.iter().enumerate() {
+        print_info("");
+        if let Err(e) = batch_process_folder(folder, config_path, options, position + 1) {
+            print_error(&format!("{}: {:#}", folder.display(), e));
+            failed.push(folder);
+        }
+    }
+    
+    print_info("");
+    print_info(&format!("Processed {} of {} folder(s)", folders.len() - failed.len(), folders.len()));
+    if !failed.is_empty() {
+        for folder in &failed {
+            print_info(&format!("  failed: {}", folder.display()));
+        }
+        bail!("{} folder(s) failed", failed.len());
+    }
+    
+    Ok(())
+}
+
+/// Collects `dir` and its subfolders (sorted, depth first) that contain `.srt` files.
+/// Folders that cannot be read are included so the error is reported when processing them.
+fn collect_srt_folders(dir: &Path, folders: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        folders.push(dir.to_path_buf());
+        return;
+    };
+    
+    let mut has_srt = false;
+    let mut subfolders = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            subfolders.push(path);
+        } else if path.extension().and_then(|s| s.to_str()) == Some("srt") {
+            has_srt = true;
+        }
+    }
+    
+    if has_srt {
+        folders.push(dir.to_path_buf());
+    }
+    subfolders.sort();
+    for subfolder in subfolders {
+        collect_srt_folders(&subfolder, folders);
+    }
+}
+
+/// `index` is the folder's 1-based position in a recursive run, used by `{index}` in --output-template
+fn batch_process_folder(dir: &Path, config_path: &Option<PathBuf>, options: &BatchOptions, index: usize) -> Result<()> {
+    let BatchOptions { yes, progress_json, .. } = *options;
+    print_info(&format!("Scanning for SRT files in: {}", dir.display()));
+    
+    let Some(plan) = plan_batch_folder(dir, options, index)? else {
+        return Ok(());
+    };
+    
+    let mut processed_files = Vec::new();
+    
+    for (source, target, label) in &plan.copies {
+        fs::copy(source, target)
+            .with_context(|| format!("Failed to copy {} to {}", source.display(), target.display()))?;
+        print_info(&format!("Renamed {} file to: {}", label, file_name_of(target)));
+        
+        processed_files.push(file_name_of(target));
+        emit_progress(progress_json, "batch", "rename", processed_files.len(), plan.copies.len());
+    }
+    
+    if let Some((en_srt_path, output_path)) = &plan.process {
+        let en_target = file_name_of(en_srt_path);
+        print_info(&format!("Processing {} for improved readability...", en_target));
+        
+        emit_progress(progress_json, "batch", "process", 0, 1);
+        let output = batch_process_command(en_srt_path, output_path, config_path).output()
+            .with_context(|| "Failed to execute srt-handle process command")?;
+        emit_progress(progress_json, "batch", "process", 1, 1);
+        
+        if output.status.success() {
+            print_success(&format!("Successfully processed {} -> {}", en_target, file_name_of(output_path)));
+            
+            // Under --strict the run is going to fail, so keep the originals around
+            if run_settings().strict && has_reported_problems() {
+                print_info("Keeping original files because warnings occurred under --strict");
+            } else if !plan.originals.is_empty() {
+                // Without a terminal nobody can answer the prompt, so refuse instead of guessing
+                if !yes && !io::stdin().is_terminal() {
+                    bail!(
+                        "Not deleting {} original files: stdin is not a terminal, so the deletion cannot be confirmed. Pass --yes to delete them without asking",
+                        plan.originals.len()
+                    );
+                }
+                delete_originals(&plan.originals, yes, progress_json, &mut io::stdin().lock())?;
+            }
+            
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("Failed to process {}: {}", en_target, stderr.trim_end());
+        }
+    }
+    
+    print_success("Batch processing completed.");
+    print_info(&format!("Processed files: {}", processed_files.join(", ")));
+    
+    Ok(())
+}
+
+/// The `srt-handle process` run batch uses for the English file. It inherits our working
+/// directory, so the paths mean the same to it as they do here.
+fn batch_process_command(input: &Path, output: &Path, config_path: &Option<PathBuf>) -> Command {
+    let mut cmd = Command::new(env::current_exe().unwrap_or_else(|_| PathBuf::from("srt-handle")));
+    cmd.arg("process").arg(input).arg("-o").arg(output);
+    
+    // Only add config argument if one was specified
+    if let Some(config_path) = config_path {
+        cmd.arg("-c").arg(config_path);
+    }
+    if run_settings().strict {
+        cmd.arg("--strict");
+    }
+    // Batch owns its standard output names, so re-runs replace the previous result
+    cmd.arg("--force");
+    if let Some(encoding) = run_settings().encoding.to_possible_value() {
+        cmd.arg("--encoding").arg(encoding.get_name());
+    }
+    cmd
+}
+
+/// Deletes the renamed originals once the user agrees (read from `answers`) or passed --yes
+fn delete_originals(originals: &[PathBuf], yes: bool, progress_json: bool, answers: &mut dyn BufRead) -> Result<()> {
+    if !yes && !confirm(&format!("Delete {} original files?", originals.len()), answers)? {
+        print_info("Keeping original files");
+        return Ok(());
+    }
+    
+    print_info("Cleaning up original files...");
+    for (done, original_file) in originals.iter().enumerate() {
+        if let Err(e) = fs::remove_file(original_file) {
+            print_warning(&format!("Failed to delete {}: {}", original_file.display(), e));
+        } else {
+            print_info(&format!("Deleted: {}", original_file.display()));
+        }
+        emit_progress(progress_json, "batch", "cleanup", done + 1, originals.len());
+    }
+    
+    Ok(())
+}
+
+/// Asks a yes/no question, reading the answer from `answers` (stdin outside tests);
+/// anything but "y"/"yes" (including end of input) means no
+fn confirm(question: &str, answers: &mut dyn BufRead) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    
+    let mut answer = String::new();
+    answers.read_line(&mut answer)?;
+    
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Checks structure, plus any `--expect-*` constraints given on the command line
+fn validate_srt_file(input: &Path, min_entries: Option<usize>, max_cps: Option<f64>, max_cue_ms: Option<u64>) -> Result<()> {
+    let content = read_input_file(input)?;
+    
+    let entries = parse_srt(&content)?;
+    
+    if entries.is_empty() {
+        bail!("{} contains no valid SRT entries", input.display());
+    }
+    
+    let problems = validation_problems(&entries, min_entries, max_cps, max_cue_ms);
+    if problems.is_empty() {
+        println!("{}: {} entries, no problems found", input.display(), entries.len());
+        return Ok(());
+    }
+    
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    bail!("{} problem(s) found in {}", problems.len(), input.display())
+}
+
+/// Duplicate indices and failed `--expect-*` constraints, one line each
+fn validation_problems(entries: &[SrtEntry], min_entries: Option<usize>, max_cps: Option<f64>, max_cue_ms: Option<u64>) -> Vec<String> {
+    let mut problems = Vec::new();
+    
+    // Downstream tools key on the index, so each value must be unique
+    let mut positions: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    for (position, entry) in entries.iter().enumerate() {
+        positions.entry(entry.index).or_default().push(position + 1);
+    }
+    for (index, found_at) in &positions {
+        if found_at.len() > 1 {
+            let found_at: Vec<String> = found_at.iter().map(|p| p.to_string()).collect();
+            problems.push(format!("Duplicate index {} at entries {}", index, found_at.join(", ")));
+        }
+    }
+    
+    // Includes hours past 99, which SRT's two-digit hour field cannot hold
+    for entry in entries.iter().filter(|entry| TimeRange::parse(&entry.timestamp).is_none()) {
+        problems.push(format!("Entry {} has an invalid timestamp line '{}'", entry.index, entry.timestamp.trim()));
+    }
+    
+    if let Some(min) = min_entries
+        && entries.len() < min {
+        problems.push(format!("--expect-min-entries {} failed: found {} entries", min, entries.len()));
+    }
+    
+    let timed: Vec<(&SrtEntry, TimeRange)> = entries.iter()
+        .filter_map(|entry| TimeRange::parse(&entry.timestamp).map(|range| (entry, range)))
+        .collect();
+    
+    if let Some(max) = max_cps {
+        let fast: Vec<(u32, f64)> = timed.iter()
+            .map(|(entry, range)| (entry.index, chars_per_second(&entry.text, range.duration_ms())))
+            .filter(|(_, cps)| *cps > max)
+            .collect();
+        if let Some((index, cps)) = fast.iter().copied().max_by(|a, b| a.1.total_cmp(&b.1)) {
+            problems.push(format!("--expect-max-cps {} failed: {} entries over, fastest is entry {} at {:.1} chars/s", max, fast.len(), index, cps));
+        }
+    }
+    
+    if let Some(max_ms) = max_cue_ms {
+        let long: Vec<(u32, u64)> = timed.iter()
+            .map(|(entry, range)| (entry.index, range.duration_ms()))
+            .filter(|(_, duration)| *duration > max_ms)
+            .collect();
+        if let Some((index, duration)) = long.iter().copied().max_by_key(|(_, duration)| *duration) {
+            problems.push(format!("--expect-max-cue {}ms failed: {} entries over, longest is entry {} at {}ms", max_ms, long.len(), index, duration));
+        }
+    }
+    
+    problems
+}
+
+/// Compares two timestamp lines within `tolerance_ms`, falling back to exact text
+/// comparison when either cannot be parsed
+fn timestamps_match(a: &str, b: &str, tolerance_ms: u64) -> bool {
+    match (TimeRange::parse(a), TimeRange::parse(b)) {
+        (Some(a), Some(b)) => a.approx_eq(&b, tolerance_ms),
+        _ => a.trim() == b.trim(),
+    }
+}
+
+fn diff_srt_files(old: &Path, new: &Path, text_only: bool, tolerance_ms: u64) -> Result<()> {
+    let old_content = read_input_file(old)?;
+    let new_content = read_input_file(new)?;
+    
+    let old_entries = parse_srt(&old_content)?;
+    let new_entries = parse_srt(&new_content)?;
+    
+    let differences = entry_differences(&old_entries, &new_entries, text_only, tolerance_ms);
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    println!("{} difference(s) between {} and {}", differences.len(), old.display(), new.display());
+    
+    Ok(())
+}
+
+/// One report per changed timing or text, aligning entries by their index rather than their position
+fn entry_differences(old_entries: &[SrtEntry], new_entries: &[SrtEntry], text_only: bool, tolerance_ms: u64) -> Vec<String> {
+    let old_by_index: BTreeMap<u32, &SrtEntry> = old_entries.iter().map(|e| (e.index, e)).collect();
+    let new_by_index: BTreeMap<u32, &SrtEntry> = new_entries.iter().map(|e| (e.index, e)).collect();
+    let indices: BTreeSet<u32> = old_by_index.keys().chain(new_by_index.keys()).copied().collect();
+    
+    let mut differences = Vec::new();
+    
+    for index in indices {
+        match (old_by_index.get(&index), new_by_index.get(&index)) {
+            (Some(old_entry), Some(new_entry)) => {
+                if !text_only && !timestamps_match(&old_entry.timestamp, &new_entry.timestamp, tolerance_ms) {
+                    differences.push(format!("{} timing: {} => {}", index, old_entry.timestamp, new_entry.timestamp));
+                }
+                if old_entry.text != new_entry.text {
+                    differences.push(format!("{} text:\n  - {}\n  + {}", index, old_entry.text, new_entry.text));
+                }
+            }
+            (Some(old_entry), None) => differences.push(format!("{} removed: {}", index, old_entry.text)),
+            (None, Some(new_entry)) => differences.push(format!("{} added: {}", index, new_entry.text)),
+            (None, None) => {}
+        }
+    }
+    
+    differences
+}
+
+/// Maps the common rounded NTSC rates to their exact 1000/1001 values
+fn exact_frame_rate(fps: f64) -> f64 {
+    for nominal in [24.0, 30.0, 60.0] {
+        let ntsc = nominal * 1000.0 / 1001.0;
+        if (fps - ntsc).abs() < 0.01 {
+            return ntsc;
+        }
+    }
+    fps
+}
+
+/// Converts a frame number to milliseconds. With `drop_per_minute` set, the number is
+/// a drop-frame timecode count, so the labels skipped each minute (except every
+/// tenth) are removed before dividing by the real frame rate.
+fn frame_to_millis(frame: u64, fps: f64, drop_per_minute: u64) -> u64 {
+    let real_frame = if drop_per_minute > 0 {
+        let frames_per_minute = fps.round() as u64 * 60;
+        let total_minutes = frame / frames_per_minute;
+        frame - drop_per_minute * (total_minutes - total_minutes / 10)
+    } else {
+        frame
+    };
+    
+    (real_frame as f64 * 1000.0 / fps).round() as u64
+}
+
+/// Number of whole frames (rounded) in `ms` milliseconds at `fps`
+fn millis_to_frames(ms: u64, fps: f64) -> u64 {
+    (ms as f64 * fps / 1000.0).round() as u64
+}
+
+fn convert_frames_to_srt(input: &Path, output: &Option<PathBuf>, fps: f64, drop_frame: bool, format: &OutputFormat) -> Result<()> {
+    if fps <= 0.0 {
+        bail!("Frame rate must be positive, got {}", fps);
+    }
+    let fps = exact_frame_rate(fps);
+    
+    let drop_per_minute = if drop_frame {
+        match fps.round() as u64 {
+            30 if fps < 30.0 => 2,
+            60 if fps < 60.0 => 4,
+            _ => bail!("Drop-frame timecode is only defined for 29.97 and 59.94 fps"),
+        }
+    } else {
+        0
+    };
+    
+    let content = read_input_file(input)?;
+    
+    let mut entries = Vec::new();
+    
+    for (line_number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        
+        let (start, rest) = line.split_once(char::is_whitespace)
+            .with_context(|| format!("Line {}: expected 'start_frame end_frame text'", line_number + 1))?;
+        let rest = rest.trim_start();
+        let (end, text) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        
+        let start: u64 = start.parse()
+            .with_context(|| format!("Line {}: invalid start frame '{}'", line_number + 1, start))?;
+        let end: u64 = end.parse()
+            .with_context(|| format!("Line {}: invalid end frame '{}'", line_number + 1, end))?;
+        if end < start {
+            bail!("Line {}: end frame {} is before start frame {}", line_number + 1, end, start);
+        }
+        
+        let range = TimeRange {
+            start: Timestamp::checked_from_millis(frame_to_millis(start, fps, drop_per_minute))
+                .with_context(|| format!("Line {}: start frame {} is out of range", line_number + 1, start))?,
+            end: Timestamp::checked_from_millis(frame_to_millis(end, fps, drop_per_minute))
+                .with_context(|| format!("Line {}: end frame {} is out of range", line_number + 1, end))?,
+        };
+        
+        entries.push(SrtEntry {
+            index: entries.len() as u32 + 1,
+            timestamp: range.to_string(),
+            text: text.trim().to_string(),
+            position_tag: None,
+            speaker: None,
+            notes: Vec::new(),
+        });
+    }
+    
+    if entries.is_empty() {
+        warn_empty_input(input, content.trim().is_empty());
+        return Ok(());
+    }
+    
+    let output_path = output.clone().unwrap_or_else(|| input.with_extension("srt"));
+    
+    write_output_file(&output_path, format_srt_output(&entries, format))?;
+    
+    print_success(&format!("Converted {} frame-numbered entries to: {}", entries.len(), output_path.display()));
+    
+    Ok(())
+}
+
+/// Total time covered by at least one range; overlapping cues are only counted once
+fn covered_millis(ranges: &[TimeRange]) -> u64 {
+    let mut spans: Vec<(u64, u64)> = ranges.iter()
+        .map(|r| (r.start.to_millis(), r.end.to_millis()))
+        .filter(|(start, end)| end > start)
+        .collect();
+    spans.sort();
+    
+    let mut total = 0;
+    let mut current: Option<(u64, u64)> = None;
+    for (start, end) in spans {
+        match current {
+            Some((cur_start, cur_end)) if start <= cur_end => current = Some((cur_start, cur_end.max(end))),
+            Some((cur_start, cur_end)) => {
+                total += cur_end - cur_start;
+                current = Some((start, end));
+            }
+            None => current = Some((start, end)),
+        }
+    }
+    if let Some((cur_start, cur_end)) = current {
+        total += cur_end - cur_start;
+    }
+    total
+}
+
+fn print_srt_stats(input: &Path, fps: Option<f64>, cps_outliers: bool) -> Result<()> {
+    if fps.is_some_and(|fps| !fps.is_finite() || fps <= 0.0) {
+        bail!("Frame rate must be positive, got {}", fps.unwrap_or_default());
+    }
+    let fps = fps.map(exact_frame_rate);
+    
+    let content = read_input_file(input)?;
+    
+    let entries = parse_srt(&content)?;
+    
+    if entries.is_empty() {
+        warn_empty_input(input, content.trim().is_empty());
+        return Ok(());
+    }
+    
+    let ranges: Vec<TimeRange> = entries.iter()
+        .filter_map(|entry| TimeRange::parse(&entry.timestamp))
+        .collect();
+    if ranges.len() < entries.len() {
+        print_warning(&format!("{} entries have unparseable timestamps and are left out of the timing stats", entries.len() - ranges.len()));
+    }
+    
+    print!("{}", format_srt_stats(input, &entries, &ranges, fps, cps_outliers));
+    
+    Ok(())
+}
+
+fn format_srt_stats(input: &Path, entries: &[SrtEntry], ranges: &[TimeRange], fps: Option<f64>, cps_outliers: bool) -> String {
+    let mut out = format!("File: {}\nEntries: {}\n", input.display(), entries.len());
+    
+    let Some(first_start) = ranges.iter().map(|r| r.start).min() else {
+        return out;
+    };
+    let last_end = ranges.iter().map(|r| r.end).max().unwrap_or(first_start);
+    
+    let span_ms = last_end.to_millis().saturating_sub(first_start.to_millis());
+    let covered_ms = covered_millis(ranges);
+    let coverage = if span_ms > 0 { covered_ms as f64 * 100.0 / span_ms as f64 } else { 0.0 };
+    
+    let duration = |ms: u64| match fps {
+        Some(fps) => format!("{} / {} frames", Timestamp::from_millis(ms), millis_to_frames(ms, fps)),
+        None => Timestamp::from_millis(ms).to_string(),
+    };
+    
+    out.push_str(&format!("Span: {} --> {} ({})\n", first_start, last_end, duration(span_ms)));
+    out.push_str(&format!("Subtitled time: {}\n", duration(covered_ms)));
+    out.push_str(&format!("Coverage: {:.1}%\n", coverage));
+    
+    let widest = entries.iter().flat_map(|entry| entry.text.lines()).map(display_width).max().unwrap_or(0);
+    out.push_str(&format!("Widest line: {} columns\n", widest));
+    
+    if cps_outliers {
+        out.push_str(&format_cps_outliers(entries));
+    }
+    
+    out
+}
+
+/// How many standard deviations above the mean reading speed `stats --cps-outliers` flags
+const CPS_OUTLIER_SIGMA: f64 = 2.0;
+
+/// Flags entries reading much faster than the rest of the file, which usually points to
+/// a timing error. Zero-length cues have no finite speed and are always listed.
+fn format_cps_outliers(entries: &[SrtEntry]) -> String {
+    let speeds: Vec<(&SrtEntry, TimeRange, f64)> = entries.iter()
+        .filter_map(|entry| TimeRange::parse(&entry.timestamp).map(|range| (entry, range)))
+        .map(|(entry, range)| (entry, range, chars_per_second(&entry.text, range.duration_ms())))
+        .collect();
+    let finite: Vec<f64> = speeds.iter().map(|(_, _, cps)| *cps).filter(|cps| cps.is_finite()).collect();
+    if finite.is_empty() {
+        return "CPS: no timed entries\n".to_string();
+    }
+    
+    let mean = finite.iter().sum::<f64>() / finite.len() as f64;
+    let std_dev = (finite.iter().map(|cps| (cps - mean).powi(2)).sum::<f64>() / finite.len() as f64).sqrt();
+    let threshold = mean + CPS_OUTLIER_SIGMA * std_dev;
+    let outliers: Vec<&(&SrtEntry, TimeRange, f64)> = speeds.iter().filter(|(_, _, cps)| *cps > threshold).collect();
+    
+    let mut out = format!("CPS: mean {:.1}, standard deviation {:.1}\n", mean, std_dev);
+    if outliers.is_empty() {
+        out.push_str(&format!("CPS outliers: none above {:.1}\n", threshold));
+        return out;
+    }
+    out.push_str(&format!("CPS outliers: {} above {:.1}\n", outliers.len(), threshold));
+    for (entry, range, cps) in outliers {
+        let cps = if cps.is_finite() { format!("{:.1}", cps) } else { "zero duration".to_string() };
+        out.push_str(&format!("  {:>6}  {}  {}\n", entry.index, range, cps));
+    }
+    
+    out
+}
+
+/// Prints the first (or with `from_end`, the last) `count` entries in SRT form,
+/// with the indices they have in the file
+fn print_entry_slice(input: &Path, count: usize, from_end: bool) -> Result<()> {
+    let content = read_input_file(input)?;
+    
+    let entries = parse_srt(&content)?;
+    print!("{}", format_entry_slice(&entries, count, from_end));
+    
+    Ok(())
+}
+
+fn format_entry_slice(entries: &[SrtEntry], count: usize, from_end: bool) -> String {
+    let count = count.min(entries.len());
+    let slice = if from_end {
+        &entries[entries.len() - count..]
+    } else {
+        &entries[..count]
+    };
+    
+    format_srt_output(slice, &OutputFormat { no_renumber: true, ..Default::default() })
+}
+
+/// Quotes a CSV field when it contains a delimiter, quote or line break (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn format_csv_export(entries: &[SrtEntry]) -> String {
+    let mut out = String::from("index,start,end,duration_ms,char_count,text\n");
+    
+    for entry in entries {
+        let (start, end, duration) = match TimeRange::parse(&entry.timestamp) {
+            Some(range) => (range.start.to_string(), range.end.to_string(), range.duration_ms().to_string()),
+            None => (String::new(), String::new(), String::new()),
+        };
+        let fields = [
+            entry.index.to_string(),
+            start,
+            end,
+            duration,
+            entry.text.chars().count().to_string(),
+            entry.text.clone(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    
+    out
+}
+
+/// Escapes backslashes, tabs and line breaks so a text stays in one TSV field
+fn tsv_field(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn format_tsv_export(entries: &[SrtEntry]) -> String {
+    entries.iter()
+        .map(|entry| {
+            let timestamp = match TimeRange::parse(&entry.timestamp) {
+                Some(range) => range.to_string(),
+                None => entry.timestamp.trim().to_string(),
+            };
+            format!("{}\t{}\n", tsv_field(&timestamp), tsv_field(&entry.text))
+        })
+        .collect()
+}
+
+fn format_vtt_export(entries: &[SrtEntry]) -> String {
+    let mut out = String::from("WEBVTT\n");
+    
+    for entry in entries {
+        if !entry.notes.is_empty() {
+            out.push_str(&format!("\nNOTE\n{}\n", entry.notes.join("\n")));
+        }
+        
+        let timestamp = match TimeRange::parse(&entry.timestamp) {
+            Some(range) => range.format(TimestampFormat::Vtt),
+            None => entry.timestamp.trim().replace(',', "."),
+        };
+        out.push_str(&format!("\n{}\n{}\n{}\n", entry.index, timestamp, entry.text));
+    }
+    
+    out
+}
+
+fn export_srt_file(input: &Path, output: &Option<PathBuf>, format: ExportFormat) -> Result<()> {
+    let content = read_input_file(input)?;
+    
+    let entries = parse_srt(&content)?;
+    
+    let exported = match format {
+        ExportFormat::Csv => format_csv_export(&entries),
+        ExportFormat::Vtt => format_vtt_export(&entries),
+        ExportFormat::Tsv => format_tsv_export(&entries),
+    };
+    
+    match output {
+        Some(output_path) => {
+            write_output_file(output_path, exported)?;
+            print_success(&format!("Exported {} entries to: {}", entries.len(), output_path.display()));
+        }
+        None => print!("{}", exported),
+    }
+    
+    Ok(())
+}
+
+/// Minimum share of letters a script needs to be reported as one of a file's languages
+const LANGUAGE_SHARE_THRESHOLD: f64 = 0.2;
+
+/// Detects languages by character script. Latin letters are reported as `en`, Han
+/// as `zh` unless kana is present (then `ja`), Hangul as `ko`, Cyrillic as `ru` and
+/// Arabic as `ar`. Returns languages above the threshold, largest share first.
+fn detect_languages(text: &str) -> Vec<(&'static str, f64)> {
+    let mut counts: BTreeMap<&'static str, usize> = BTreeMap::new();
+    let mut han = 0;
+    let mut kana = 0;
+    
+    for c in text.chars() {
+        let script = match c as u32 {
+            0x3040..=0x30FF => {
+                kana += 1;
+                continue;
+            }
+            0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => {
+                han += 1;
+                continue;
+            }
+            0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7A3 => "ko",
+            0x0400..=0x04FF => "ru",
+            0x0600..=0x06FF => "ar",
+            _ if c.is_alphabetic() && (c.is_ascii() || (0xC0..=0x24F).contains(&(c as u32))) => "en",
+            _ => continue,
+        };
+        *counts.entry(script).or_default() += 1;
+    }
+    
+    if kana > 0 {
+        *counts.entry("ja").or_default() += kana + han;
+    } else if han > 0 {
+        *counts.entry("zh").or_default() += han;
+    }
+    
+    let total: usize = counts.values().sum();
+    if total == 0 {
+        return Vec::new();
+    }
+    
+    let mut languages: Vec<(&'static str, f64)> = counts.into_iter()
+        .map(|(code, count)| (code, count as f64 / total as f64))
+        .filter(|(_, share)| *share >= LANGUAGE_SHARE_THRESHOLD)
+        .collect();
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn scan_languages(dir: &Path, json: bool) -> Result<()> {
+    print!("{}", format_language_report(dir, json)?);
+    Ok(())
+}
+
+fn format_language_report(dir: &Path, json: bool) -> Result<String> {
+    let mut srt_paths: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("srt"))
+        .collect();
+    srt_paths.sort();
+    
+    let mut reports = Vec::new();
+    
+    for path in &srt_paths {
+        let content = match read_input_file(path) {
+            Ok(content) => content,
+            Err(e) => {
+                print_warning(&format!("Failed to read {}: {}", path.display(), e));
+                continue;
+            }
+        };
+        
+        let entries = parse_srt(&content)?;
+        let text: String = entries.iter().map(|e| e.text.as_str()).collect::<Vec<_>>().join("\n");
+        reports.push((path, entries.len(), detect_languages(&text)));
+    }
+    
+    if json {
+        let items: Vec<String> = reports.iter()
+            .map(|(path, count, languages)| {
+                let languages: Vec<String> = languages.iter()
+                    .map(|(code, share)| format!("{{\"code\":\"{}\",\"share\":{:.3}}}", code, share))
+                    .collect();
+                format!("{{\"file\":{},\"entries\":{},\"languages\":[{}]}}",
+                    json_string(&path.display().to_string()), count, languages.join(","))
+            })
+            .collect();
+        return Ok(format!("[{}]\n", items.join(",")));
+    }
+    
+    if reports.is_empty() {
+        return Ok("No SRT files found in directory.\n".to_string());
+    }
+    
+    let mut out = String::new();
+    for (path, count, languages) in &reports {
+        let summary = if languages.is_empty() {
+            "unknown".to_string()
+        } else {
+            languages.iter()
+                .map(|(code, share)| format!("{} {:.0}%", code, share * 100.0))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        out.push_str(&format!("{}: {} ({} entries)\n", path.display(), summary, count));
+    }
+    
+    Ok(out)
+}
+
+fn rebase_srt_file(input: &Path, output: &Option<PathBuf>, format: &OutputFormat) -> Result<()> {
+    let content = read_input_file(input)?;
+    
+    let mut entries = parse_srt(&content)?;
+    
+    let Some(first) = entries.iter().find_map(|e| TimeRange::parse(&e.timestamp)) else {
+        warn_empty_input(input, content.trim().is_empty());
+        return Ok(());
+    };
+    let offset = first.start.to_millis() as i64;
+    
+    for entry in entries.iter_mut() {
+        match TimeRange::parse(&entry.timestamp) {
+            Some(range) => {
+                let rebased = TimeRange { start: range.start.shifted(-offset), end: range.end.shifted(-offset) };
+                entry.timestamp = rebased.to_string();
+            }
+            None => print_warning(&format!("Entry {} has an unparseable timestamp, leaving it unchanged", entry.index)),
+        }
+    }
+    
+    let output_path = output.clone().unwrap_or_else(|| {
+        let mut path = input.to_path_buf();
+        if let Some(stem) = path.file_stem() {
+            path.set_file_name(format!("{}_rebased.srt", stem.to_string_lossy()));
+        }
+        path
+    });
+    
+    write_output_file(&output_path, format_srt_output(&entries, format))?;
+    
+    print_success(&format!("Shifted {} entries back by {} and saved to: {}", entries.len(), first.start, output_path.display()));
+    
+    Ok(())
+}
+
+/// Rules that never matched, given the SKIP words that did and each other rule's hit count
+fn unused_rules(config: &Config, skip_hits: &BTreeSet<String>, combine_hits: &[usize], combine_equal_hits: &[usize], end_hits: &[usize]) -> Vec<String> {
+    let mut unused: Vec<String> = config.skip_words.iter()
+        .filter(|word| !skip_hits.contains(*word))
+        .map(|word| format!("SKIP \"{}\"", word))
+        .collect();
+    unused.extend(config.combine_phrases.iter().zip(combine_hits)
+        .filter(|(_, hits)| **hits == 0)
+        .map(|((first, second), _)| format!("COMBINE \"{} {}\"", first, second)));
+    unused.extend(config.combine_equal.iter().zip(combine_equal_hits)
+        .filter(|(_, hits)| **hits == 0)
+        .map(|(phrase, _)| format!("COMBINE_EQUAL \"{}\"", phrase)));
+    unused.extend(config.end_words.iter().zip(end_hits)
+        .filter(|(_, hits)| **hits == 0)
+        .map(|(word, _)| format!("END \"{}\"", word)));
+    unused
+}
+
+/// Fails if `output` does not parse back into as many entries as were formatted into it
+fn verify_roundtrip(entries: &[SrtEntry], output: &str) -> Result<()> {
+    let reparsed = parse_srt(output)?;
+    if reparsed.len() != entries.len() {
+        bail!(
+            "Round-trip check failed: wrote {} entries but the output parses to {}; output not written",
+            entries.len(),
+            reparsed.len()
+        );
+    }
+    Ok(())
+}
+
+/// Pairs the entries of two monolingual files into bilingual entries. Entries without a
+/// partner are kept on their own, in time order.
+fn interleave_srt_files(first: &Path, second: &Path, output: &Option<PathBuf>, options: &MergeOptions, format: &OutputFormat) -> Result<()> {
+    let first_entries = parse_srt(&read_input_file(first)?)?;
+    let second_entries = parse_srt(&read_input_file(second)?)?;
+    
+    let mut merged_entries = Vec::new();
+    let mut paired = 0;
+    let (mut i, mut j) = (0, 0);
+    
+    while i < first_entries.len() && j < second_entries.len() {
+        let (a, b) = (&first_entries[i], &second_entries[j]);
+        let ranges = TimeRange::parse(&a.timestamp).zip(TimeRange::parse(&b.timestamp));
+        
+        let is_pair = match options.pair_by {
+            PairBy::Index => true,
+            PairBy::Timestamp => timestamps_match(&a.timestamp, &b.timestamp, options.timestamp_tolerance_ms),
+        };
+        
+        if is_pair {
+            merged_entries.push(merge_entry_group(&[a, b], options));
+            paired += 1;
+            i += 1;
+            j += 1;
+        } else if ranges.is_none_or(|(ra, rb)| ra.start <= rb.start) {
+            print_warning(&format!("Entry {} of {} has no partner, keeping it unmerged", a.index, first.display()));
+            merged_entries.push(a.clone());
+            i += 1;
+        } else {
+            print_warning(&format!("Entry {} of {} has no partner, keeping it unmerged", b.index, second.display()));
+            merged_entries.push(b.clone());
+            j += 1;
+        }
+    }
+    
+    for (entries, rest, path) in [(&first_entries, i, first), (&second_entries, j, second)] {
+        for entry in &entries[rest..] {
+            print_warning(&format!("Entry {} of {} has no partner, keeping it unmerged", entry.index, path.display()));
+            merged_entries.push(entry.clone());
+        }
+    }
+    
+    let output_path = output.clone().unwrap_or_else(|| {
+        let mut path = first.to_path_buf();
+        if let Some(stem) = path.file_stem() {
+            path.set_file_name(format!("{}_interleaved.srt", stem.to_string_lossy()));
+        }
+        path
+    });
+    
+    write_output_file(&output_path, format_srt_output(&merged_entries, format))?;
+    
+    print_success(&format!("Interleaved bilingual SRT saved to: {}", output_path.display()));
+    print_info(&format!("Paired {} entries into {} entries", paired, merged_entries.len()));
+    
+    Ok(())
+}
+
+/// Reading speed of `text` shown for `duration_ms`, ignoring line breaks. Characters are
+/// counted by display width, so a CJK character weighs as much as two Latin letters.
+fn chars_per_second(text: &str, duration_ms: u64) -> f64 {
+    let chars: usize = text.lines().map(display_width).sum();
+    if duration_ms > 0 { chars as f64 * 1000.0 / duration_ms as f64 } else { f64::INFINITY }
+}
+
+/// Reading speed above which an entry is flagged by --speed-report
+const MAX_CHARS_PER_SECOND: f64 = 20.0;
+
+/// Line width (in columns, CJK counting as 2) above which an entry is flagged by --speed-report
+const MAX_LINE_WIDTH: usize = 42;
+
+/// Prints entries that break the reading speed, minimum duration or line width limits.
+/// Each limit contributes how far it is exceeded (e.g. 1.5 for 30 cps) to the severity.
+/// Rows use the index each entry has in the written file.
+fn format_speed_report(entries: &[SrtEntry], min_cue_ms: u64, format: &OutputFormat) -> String {
+    let mut flagged = Vec::new();
+    
+    for (position, entry) in entries.iter().enumerate() {
+        let Some(range) = TimeRange::parse(&entry.timestamp) else {
+            continue;
+        };
+        let duration_ms = range.duration_ms();
+        let cps = chars_per_second(&entry.text, duration_ms);
+        let width = entry.text.lines().map(display_width).max().unwrap_or(0);
+        
+        let mut flags = Vec::new();
+        let mut severity = 0.0;
+        if cps > MAX_CHARS_PER_SECOND {
+            flags.push("fast");
+            severity += (cps / MAX_CHARS_PER_SECOND).min(10.0);
+        }
+        if duration_ms < min_cue_ms {
+            flags.push("short");
+            severity += (min_cue_ms as f64 / duration_ms.max(1) as f64).min(10.0);
+        }
+        if width > MAX_LINE_WIDTH {
+            flags.push("long-line");
+            severity += width as f64 / MAX_LINE_WIDTH as f64;
+        }
+        
+        if !flags.is_empty() {
+            flagged.push((severity, position, range, cps, width, flags));
+        }
+    }
+    
+    if flagged.is_empty() {
+        return "Speed report: no entries need attention\n".to_string();
+    }
+    
+    flagged.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    
+    let mut out = format!("Speed report: {} entries need attention\n", flagged.len());
+    out.push_str(&format!("{:>6}  {:<29}  {:>6}  {:>5}  flags\n", "entry", "time", "cps", "width"));
+    for (_, position, range, cps, width, flags) in &flagged {
+        let cps = if cps.is_finite() { format!("{:.1}", cps) } else { "-".to_string() };
+        out.push_str(&format!("{:>6}  {:<29}  {:>6}  {:>5}  {}\n", format.output_index(*position, &entries[*position]), range.to_string(), cps, width, flags.join(",")));
+    }
+    
+    out
+}
+
+/// Splits text after sentence-ending punctuation. ASCII terminators only end a sentence
+/// when followed by whitespace, so `3.5` and `e.g.x` stay intact; closing quotes and
+/// brackets stay with the sentence they close.
+fn split_sentences(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    let mut i = 0;
+    
+    while i < chars.len() {
+        let c = chars[i];
+        current.push(c);
+        i += 1;
+        if !matches!(c, '.' | '!' | '?' | '…' | '。' | '！' | '？') {
+            continue;
+        }
+        while i < chars.len() && matches!(chars[i], '.' | '!' | '?' | '"' | '\'' | '”' | '’' | ')' | '」' | '』' | '）') {
+            current.push(chars[i]);
+            i += 1;
+        }
+        if i == chars.len() || chars[i].is_whitespace() || is_cjk_char(c) {
+            let sentence = current.trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence.to_string());
+            }
+            current.clear();
+        }
+    }
+    
+    let rest = current.trim();
+    if !rest.is_empty() {
+        sentences.push(rest.to_string());
+    }
+    sentences
+}
+
+/// Concatenates all cue text and splits it into sentences, dividing the original overall
+/// time range between them in proportion to their length.
+fn resegment_srt_file(input: &Path, by: ResegmentBy, output: &Option<PathBuf>, format: &OutputFormat) -> Result<()> {
+    let content = read_input_file(input)?;
+    let entries = parse_srt(&content)?;
+    
+    let ranges: Vec<TimeRange> = entries.iter().filter_map(|e| TimeRange::parse(&e.timestamp)).collect();
+    let (Some(first), Some(last)) = (ranges.first(), ranges.last()) else {
+        warn_empty_input(input, content.trim().is_empty());
+        return Ok(());
+    };
+    let start_ms = first.start.to_millis();
+    let total_ms = last.end.to_millis().saturating_sub(start_ms);
+    
+    let mut text = String::new();
+    for line in entries.iter().flat_map(|e| e.text.lines()).map(str::trim).filter(|l| !l.is_empty()) {
+        // CJK text runs on without spaces between cues
+        let cjk_join = text.chars().last().is_some_and(is_cjk_char) && line.chars().next().is_some_and(is_cjk_char);
+        if !text.is_empty() && !cjk_join {
+            text.push(' ');
+        }
+        text.push_str(line);
+    }
+    
+    let segments = match by {
+        ResegmentBy::Sentence => split_sentences(&text),
+    };
+    let total_chars: usize = segments.iter().map(|s| s.chars().count()).sum::<usize>().max(1);
+    
+    let mut resegmented = Vec::new();
+    let mut chars_before = 0;
+    for (i, segment) in segments.into_iter().enumerate() {
+        let seg_start = start_ms + total_ms * chars_before as u64 / total_chars as u64;
+        chars_before += segment.chars().count();
+        let seg_end = start_ms + total_ms * chars_before as u64 / total_chars as u64;
+        let range = TimeRange { start: Timestamp::from_millis(seg_start), end: Timestamp::from_millis(seg_end) };
+        resegmented.push(SrtEntry {
+            index: i as u32 + 1,
+            timestamp: range.to_string(),
+            text: segment,
+            position_tag: None,
+            speaker: None,
+            notes: Vec::new(),
+        });
+    }
+    
+    let output_path = output.clone().unwrap_or_else(|| {
+        let mut path = input.to_path_buf();
+        if let Some(stem) = path.file_stem() {
+            path.set_file_name(format!("{}_resegmented.srt", stem.to_string_lossy()));
+        }
+        path
+    });
+    
+    write_output_file(&output_path, format_srt_output(&resegmented, format))?;
+    
+    print_success(&format!("Resegmented {} entries into {} and saved to: {}", entries.len(), resegmented.len(), output_path.display()));
+    
+    Ok(())
+}
+
+/// Finds a frame rate token in a file name: `23.976fps`, `25fps`, `24p` or a bare
+/// NTSC rate such as `29.97`. Bare whole numbers are ignored since they are usually
+/// episode or year numbers, and a token must stand on its own: `HDR10p` or
+/// `x264-10bit` are not frame rates.
+fn fps_from_file_name(path: &Path) -> Option<f64> {
+    let name = path.file_stem()?.to_string_lossy();
+    // The character after the token is captured rather than required, so the separator
+    // that ends one number can still start the next one ("s01e02_25p")
+    let re = Regex::new(r"(?i)(?:^|[^\da-z])(\d{2}(?:\.\d{1,3})?)(fps|p)?([\da-z])?").unwrap();
+    
+    for caps in re.captures_iter(&name) {
+        if caps.get(3).is_some() {
+            continue;
+        }
+        let value = &caps[1];
+        let has_suffix = caps.get(2).is_some();
+        if has_suffix || matches!(value, "23.976" | "23.98" | "29.97" | "59.94") {
+            let fps: f64 = value.parse().ok()?;
+            if (10.0..=120.0).contains(&fps) {
+                return Some(fps);
+            }
+        }
+    }
+    None
+}
+
+/// Multiplies every timestamp by `from_fps / to_fps`, so cues timed for a video sped up
+/// to `from_fps` line up with the same video played at `to_fps`
+fn scale_srt_file(input: &Path, output: &Option<PathBuf>, from_fps: f64, to_fps: f64, format: &OutputFormat) -> Result<()> {
+    if from_fps <= 0.0 || to_fps <= 0.0 {
+        bail!("Frame rates must be positive");
+    }
+    let factor = exact_frame_rate(from_fps) / exact_frame_rate(to_fps);
+    
+    let content = read_input_file(input)?;
+    let mut entries = parse_srt(&content)?;
+    
+    let scale = |ts: Timestamp| Timestamp::checked_from_millis((ts.to_millis() as f64 * factor).round() as u64);
+    for entry in entries.iter_mut() {
+        match TimeRange::parse(&entry.timestamp) {
+            Some(range) => {
+                let end = scale(range.end).with_context(|| format!("Entry {} cannot be scaled", entry.index))?;
+                let scaled = TimeRange { start: scale(range.start)?, end };
+                entry.timestamp = scaled.to_string();
+            }
+            None => print_warning(&format!("Entry {} has an unparseable timestamp, leaving it unchanged", entry.index)),
+        }
+    }
+    
+    let output_path = output.clone().unwrap_or_else(|| {
+        let mut path = input.to_path_buf();
+        if let Some(stem) = path.file_stem() {
+            path.set_file_name(format!("{}_scaled.srt", stem.to_string_lossy()));
+        }
+        path
+    });
+    
+    write_output_file(&output_path, format_srt_output(&entries, format))?;
+    
+    print_success(&format!("Scaled {} entries from {} to {} fps and saved to: {}", entries.len(), from_fps, to_fps, output_path.display()));
+    
+    Ok(())
+}
+
+/// Output positions of cues outside the `--spec-*` limits, with what each one breaks
+fn spec_violations(entries: &[SrtEntry], options: &ProcessOptions) -> Vec<(usize, Vec<String>)> {
+    let mut violations = Vec::new();
+    for (position, entry) in entries.iter().enumerate() {
+        let mut problems = Vec::new();
+        if let Some(range) = TimeRange::parse(&entry.timestamp)
+            && range.duration_ms() > options.spec_max_duration {
+            problems.push(format!("lasts {}ms (max {}ms)", range.duration_ms(), options.spec_max_duration));
+        }
+        let chars = entry.text.chars().filter(|c| *c != '\n').count();
+        if chars > options.spec_max_chars {
+            problems.push(format!("has {} characters (max {})", chars, options.spec_max_chars));
+        }
+        let lines = entry.text.lines().count();
+        if lines > options.spec_max_lines {
+            problems.push(format!("has {} lines (max {})", lines, options.spec_max_lines));
+        }
+        if !problems.is_empty() {
+            violations.push((position, problems));
+        }
+    }
+    violations
+}
+
+/// Line diff of the few lines belonging to one cue, via a longest common subsequence
+fn diff_lines(old: &[String], new: &[String]) -> Vec<(char, String)> {
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push((' ', old[i].clone()));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', old[i].clone()));
+            i += 1;
+        } else {
+            ops.push(('+', new[j].clone()));
+            j += 1;
+        }
+    }
+    ops
+}
+
+/// Lines of context kept around each change in `--diff` output
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// Unified diff between the input cues and the processed cues. Every pass keeps an
+/// entry's original index, so cues are aligned by it and rendered with it; renumbering
+/// therefore never shows up as a change.
+fn unified_cue_diff(old: &[SrtEntry], new: &[SrtEntry], old_path: &Path, new_path: &Path, format: &OutputFormat) -> String {
+    let render_format = OutputFormat { no_renumber: true, pad_indices: format.pad_indices, timestamp_format: format.timestamp_format, keep_index_comments: format.keep_index_comments, preserve_trailing_spaces: format.preserve_trailing_spaces, ..OutputFormat::default() };
+    let render = |entry: &SrtEntry| -> Vec<String> {
+        let mut lines: Vec<String> = format_srt_entry(0, entry, &render_format).lines().map(str::to_string).collect();
+        lines.push(String::new());
+        lines
+    };
+    
+    // (' ' | '-' | '+', line) for the whole file
+    let mut ops: Vec<(char, String)> = Vec::new();
+    let mut j = 0;
+    for old_entry in old {
+        let start = j;
+        while j < new.len() && new[j].index == old_entry.index {
+            j += 1;
+        }
+        let old_lines = render(old_entry);
+        let new_lines: Vec<String> = new[start..j].iter().flat_map(render).collect();
+        ops.extend(diff_lines(&old_lines, &new_lines));
+        // Cues whose index is out of order in the output are shown as additions where they appear
+        while j < new.len() && !old.iter().any(|e| e.index == new[j].index) {
+            ops.extend(render(&new[j]).into_iter().map(|line| ('+', line)));
+            j += 1;
+        }
+    }
+    ops.extend(new[j..].iter().flat_map(render).map(|line| ('+', line)));
+    
+    let mut diff = format!("--- {}\n+++ {}\n", old_path.display(), new_path.display());
+    let changes: Vec<usize> = ops.iter().enumerate().filter(|(_, (op, _))| *op != ' ').map(|(i, _)| i).collect();
+    let mut k = 0;
+    while k < changes.len() {
+        let hunk_start = changes[k].saturating_sub(DIFF_CONTEXT_LINES);
+        let mut hunk_end = changes[k];
+        while k < changes.len() && changes[k] <= hunk_end + 2 * DIFF_CONTEXT_LINES {
+            hunk_end = changes[k];
+            k += 1;
+        }
+        let hunk_end = (hunk_end + DIFF_CONTEXT_LINES + 1).min(ops.len());
+        
+        let count = |skip: char, range: std::ops::Range<usize>| ops[range].iter().filter(|(op, _)| *op != skip).count();
+        let old_before = count('+', 0..hunk_start);
+        let new_before = count('-', 0..hunk_start);
+        let old_len = count('+', hunk_start..hunk_end);
+        let new_len = count('-', hunk_start..hunk_end);
+        diff.push_str(&format!("@@ -{},{} +{},{} @@\n", old_before + 1, old_len, new_before + 1, new_len));
+        for (op, line) in &ops[hunk_start..hunk_end] {
+            diff.push_str(&format!("{}{}\n", op, line));
+        }
+    }
+    diff
+}
+
+/// Per-entry text cleanup that runs before any rules: mojibake repair, --dedup-lines,
+/// --flatten, speaker labels and punctuation. Returns how many entries had mojibake repaired.
+fn apply_text_cleanup(entries: &mut [SrtEntry], options: &ProcessOptions, speaker_label: Option<&Regex>) -> usize {
+    let repaired = if options.fix_mojibake { apply_mojibake_repair(entries) } else { 0 };
+    
+    for entry in entries.iter_mut() {
+        if options.dedup_lines {
+            entry.text = dedup_consecutive_lines(&entry.text);
+        }
+        if options.flatten {
+            entry.text = entry.text.split('\n').map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>().join(" ");
+        }
+        if let Some(pattern) = speaker_label {
+            entry.text = strip_speaker_label(&entry.text, pattern);
+        }
+        if let Some(style) = options.normalize_punctuation {
+            entry.text = normalize_punctuation(&entry.text, style);
+        }
+    }
+    
+    repaired
+}
+
+/// Drops a line that repeats the line right before it (ignoring surrounding whitespace)
+fn dedup_consecutive_lines(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.split('\n') {
+        if lines.last().is_none_or(|previous| previous.trim() != line.trim() || line.trim().is_empty()) {
+            lines.push(line);
+        }
+    }
+    lines.join("\n")
+}
+
+/// Applies SKIP rules to one entry and returns whether it is kept. With `skip_lines` only
+/// the matching lines are removed; with `invert` the SKIP list acts as a keep list.
+fn apply_skip(entry: &mut SrtEntry, skip_lines: bool, invert: bool, matches_skip: &mut dyn FnMut(&str) -> bool) -> bool {
+    if !skip_lines {
+        return matches_skip(&entry.text) == invert;
+    }
+    
+    let kept: Vec<&str> = entry.text.lines()
+        .filter(|line| matches_skip(line) == invert)
+        .collect();
+    // The cue is only dropped when every one of its lines matched
+    if kept.is_empty() && !entry.text.trim().is_empty() {
+        return false;
+    }
+    entry.text = kept.join("\n");
+    true
+}
+
+/// Entries with unparseable timestamps never count as short since their duration is unknown
+fn is_shorter_than(entry: &SrtEntry, min_ms: u64) -> bool {
+    TimeRange::parse(&entry.timestamp).is_some_and(|range| range.duration_ms() < min_ms)
+}
+
+/// Names the first option that needs the whole file in memory, or `None` when every
+/// requested pass works one entry at a time
+fn streaming_blocker(input: &Path, config: &Config, options: &ProcessOptions) -> Result<Option<&'static str>> {
+    let needs_whole_file = [
+        (!config.combine_phrases.is_empty(), "COMBINE rules"),
+        (!config.combine_equal.is_empty(), "COMBINE_EQUAL rules"),
+        (!config.end_words.is_empty(), "END rules"),
+        (options.lyrics, "--lyrics"),
+        (options.check, "--check"),
+        (options.detect_bilingual, "--detect-bilingual"),
+        (options.dedup_overlap, "--dedup-overlap"),
+        (options.merge_same_speaker, "--merge-same-speaker"),
+        (options.join_short, "--join-short"),
+        (options.autotime, "--autotime"),
+        (options.verify_roundtrip, "--verify-roundtrip"),
+        (options.strict_srt, "--strict-srt"),
+        (options.speed_report, "--speed-report"),
+        (options.report_unused, "--report-unused"),
+        (options.audit.is_some(), "--audit"),
+        (options.diff, "--diff"),
+    ];
+    if let Some((_, name)) = needs_whole_file.iter().find(|(needed, _)| *needed) {
+        return Ok(Some(name));
+    }
+    
+    // Streaming reads UTF-8 lines, so UTF-16 input goes through the in-memory decoder
+    let mut head = Vec::new();
+    fs::File::open(input)
+        .with_context(|| format!("Failed to read input file: {}", input.display()))?
+        .take(4096)
+        .read_to_end(&mut head)?;
+    let encoding = match run_settings().encoding {
+        InputEncoding::Auto => detect_encoding(&head),
+        other => other,
+    };
+    Ok(matches!(encoding, InputEncoding::Utf16le | InputEncoding::Utf16be).then_some("UTF-16 input"))
+}
+
+/// Processes a file one block at a time, writing each entry as soon as it is done so
+/// memory stays bounded. Only valid when `streaming_blocker` returns `None`. Returns the
+/// number of mojibake repairs and of entries dropped by --skip-shorter-than, or `None`
+/// (after a warning) when the input has no entries, in which case nothing is written.
+fn stream_process_file(input: &Path, output_path: &Path, config: &Config, matcher: &dyn Matcher, speaker_label: Option<&Regex>, options: &ProcessOptions) -> Result<Option<(usize, usize)>> {
+    if input.is_dir() {
+        bail!("Input {} is a directory; did you mean `srt-handle batch -d {}`?", input.display(), input.display());
+    }
+    let reader = io::BufReader::new(fs::File::open(input)
+        .with_context(|| format!("Input file not found: {}", input.display()))?);
+    
+    let mut matches_skip = |text: &str| find_skip_word(text, &config.skip_words, matcher).is_some();
+    let (mut repaired, mut dropped_short, mut written) = (0, 0, 0);
+    // The output is only created once the first entry is parsed, so an empty input leaves
+    // no file behind, as in memory
+    let mut writer: Option<io::BufWriter<fs::File>> = None;
+    let mut blank = true;
+    
+    let mut process_entry = |entry: SrtEntry| -> Result<()> {
+        let writer = match &mut writer {
+            Some(writer) => writer,
+            None => {
+                check_output_path(output_path)?;
+                writer.insert(io::BufWriter::new(fs::File::create(output_path)
+                    .with_context(|| format!("Failed to write output file: {}", output_path.display()))?))
+            }
+        };
+        let mut entries = vec![entry];
+        repaired += apply_text_cleanup(&mut entries, options, speaker_label);
+        entries.retain_mut(|entry| apply_skip(entry, options.skip_lines, options.invert_skip, &mut matches_skip));
+        if let Some(min_ms) = options.skip_shorter_than {
+            let before = entries.len();
+            entries.retain(|entry| !is_shorter_than(entry, min_ms));
+            dropped_short += before - entries.len();
+        }
+        if let Some(max_ms) = options.max_cue_duration {
+            apply_max_cue_duration(&mut entries, max_ms, options.long_cue_split);
+        }
+        if let Some(max_chars) = options.truncate_chars {
+            apply_truncation(&mut entries, max_chars, &options.ellipsis);
+        }
+        
+        for entry in &entries {
+            let separator = if written > 0 { "\n" } else { "" };
+            write!(writer, "{}{}", separator, format_srt_entry(written, entry, &options.format))?;
+            written += 1;
+        }
+        Ok(())
+    };
+    
+    let mut parser = BlockParser::default();
+    // Lines up to the first cue are kept so the preamble is stripped just as `parse_srt` does
+    let mut head = Some(String::new());
+    let mut block: Vec<String> = Vec::new();
+    let mut lines = reader.lines();
+    loop {
+        let line = lines.next().transpose()
+            .with_context(|| format!("Failed to read input file: {}", input.display()))?;
+        let at_end = line.is_none();
+        
+        match line {
+            Some(line) if !line.is_empty() => {
+                blank &= line.trim().is_empty();
+                block.push(line);
+                continue;
+            }
+            _ => {}
+        }
+        
+        let lines: Vec<&str> = block.iter().map(String::as_str).collect();
+        if let Some(text) = head.as_mut() {
+            text.extend(lines.iter().map(|line| format!("{}\n", line)));
+            text.push('\n');
+            if at_end || (0..lines.len()).any(|i| starts_block(&lines[i..])) {
+                for lines in split_blocks(strip_preamble(text)) {
+                    if let Some(entry) = parser.push(&lines) {
+                        process_entry(entry)?;
+                    }
+                }
+                head = None;
+            }
+        } else if let Some(entry) = parser.push(&lines) {
+            process_entry(entry)?;
+        }
+        block.clear();
+        
+        if at_end {
+            break;
+        }
+    }
+    
+    if let Some(last) = parser.finish() {
+        process_entry(last)?;
+    }
+    let Some(mut writer) = writer else {
+        warn_empty_input(input, blank);
+        return Ok(None);
+    };
+    writer.flush()
+        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+    
+    Ok(Some((repaired, dropped_short)))
+}
+
+/// Adds `delta_ms` to every timestamp, clamping at zero. Returns how many entries had a
+/// start clamped, since those now pile up at 00:00:00,000.
+fn shift_timestamps(entries: &mut [SrtEntry], delta_ms: i64) -> Result<usize> {
+    let mut clamped = 0;
+    for entry in entries.iter_mut() {
+        match TimeRange::parse(&entry.timestamp) {
+            Some(range) => {
+                if (range.start.to_millis() as i64) + delta_ms < 0 {
+                    clamped += 1;
+                }
+                Timestamp::checked_from_millis(range.end.to_millis().saturating_add_signed(delta_ms))
+                    .with_context(|| format!("Entry {} cannot be shifted by {}ms", entry.index, delta_ms))?;
+                let shifted = TimeRange { start: range.start.shifted(delta_ms), end: range.end.shifted(delta_ms) };
+                entry.timestamp = shifted.to_string();
+            }
+            None => print_warning(&format!("Entry {} has an unparseable timestamp, leaving it unchanged", entry.index)),
+        }
+    }
+    Ok(clamped)
+}
+
+fn shift_srt_file(input: &Path, delta_ms: i64, output: &Option<PathBuf>, format: &OutputFormat) -> Result<()> {
+    let content = read_input_file(input)?;
+    let mut entries = parse_srt(&content)?;
+    
+    if entries.is_empty() {
+        warn_empty_input(input, content.trim().is_empty());
+        return Ok(());
+    }
+    
+    let clamped = shift_timestamps(&mut entries, delta_ms)?;
+    if clamped > 0 {
+        print_warning(&format!("{} entries would start before 00:00:00,000 and were clamped to zero; they may overlap and need manual review", clamped));
+    }
+    
+    let output_path = output.clone().unwrap_or_else(|| {
+        let mut path = input.to_path_buf();
+        if let Some(stem) = path.file_stem() {
+            path.set_file_name(format!("{}_shifted.srt", stem.to_string_lossy()));
+        }
+        path
+    });
+    
+    write_output_file(&output_path, format_srt_output(&entries, format))?;
+    
+    print_success(&format!("Shifted {} entries by {}ms and saved to: {}", entries.len(), delta_ms, output_path.display()));
+    
+    Ok(())
+}
+
+/// Writes one file per run of cues separated by gaps longer than `min_gap_ms`, named
+/// `<stem>_<HH-MM-SS>.srt` after the segment's start. Timestamps are left as they are.
+fn segment_srt_file(input: &Path, min_gap_ms: u64, out_dir: &Option<PathBuf>, format: &OutputFormat) -> Result<()> {
+    let content = read_input_file(input)?;
+    let entries = parse_srt(&content)?;
+    
+    if entries.is_empty() {
+        warn_empty_input(input, content.trim().is_empty());
+        return Ok(());
+    }
+    
+    let mut segments: Vec<Vec<SrtEntry>> = vec![Vec::new()];
+    let mut previous_end: Option<Timestamp> = None;
+    for entry in entries {
+        let range = TimeRange::parse(&entry.timestamp);
+        if let (Some(range), Some(end)) = (range, previous_end)
+            && range.start.to_millis().saturating_sub(end.to_millis()) > min_gap_ms {
+            segments.push(Vec::new());
+        }
+        if let Some(range) = range {
+            previous_end = Some(range.end);
+        }
+        segments.last_mut().unwrap().push(entry);
+    }
+    
+    let dir = match out_dir {
+        Some(dir) => dir.clone(),
+        None => input.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    let stem = input.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| "segment".to_string());
+    
+    for (number, segment) in segments.iter().enumerate() {
+        let start = segment.iter().find_map(|e| TimeRange::parse(&e.timestamp)).map_or(Timestamp::from_millis(0), |range| range.start);
+        let path = dir.join(segment_file_name(&stem, number + 1, segments.len(), start));
+        write_output_file(&path, format_srt_output(segment, format))?;
+        print_info(&format!("{} ({} entries)", path.display(), segment.len()));
+    }
+    
+    print_success(&format!("Split {} into {} segment(s) at gaps over {}ms", input.display(), segments.len(), min_gap_ms));
+    
+    Ok(())
+}
+
+/// `<stem>_<number>_<HH-MM-SS-mmm>.srt`. The number keeps names unique even when two
+/// segments start at the same time, and is padded so the files sort in order.
+fn segment_file_name(stem: &str, number: usize, count: usize, start: Timestamp) -> String {
+    format!("{}_{:0width$}_{:02}-{:02}-{:02}-{:03}.srt", stem, number, start.hours, start.minutes, start.seconds, start.millis, width = count.to_string().len())
+}
+
+/// Small cue set for `doctor`: one SKIP match, one COMBINE pair and one END word
+const DOCTOR_SAMPLE: &str = "1\n00:00:01,000 --> 00:00:02,000\n[music]\n\n2\n00:00:02,000 --> 00:00:03,000\nI want to thank\n\n3\n00:00:03,000 --> 00:00:04,000\nyou all so much and I\n\n4\n00:00:04,000 --> 00:00:05,000\nmean it\n";
+
+/// Reports the version, the embedded config's rule counts and the result of processing a
+/// built-in sample, failing if any check fails
+fn run_doctor() -> Result<()> {
+    println!("srt-handle {}", env!("CARGO_PKG_VERSION"));
+    
+    let config = Config::from_embedded().context("Embedded config.txt does not parse")?;
+    print_success("Embedded config parses");
+    print!("{}", format_rule_counts(&config));
+    
+    let mut entries = parse_srt(DOCTOR_SAMPLE)?;
+    let parsed = entries.len();
+    entries.retain(|entry| find_skip_word(&entry.text, &config.skip_words, &DefaultMatcher::default()).is_none());
+    let mut audit = Vec::new();
+    apply_combine_rules(&mut entries, &config.combine_phrases, &CombineOptions::default(), &DefaultMatcher::default(), &mut audit);
+    apply_end_rules(&mut entries, &config.end_words, false, false, &DefaultMatcher::default(), &mut audit);
+    
+    let output = format_srt_output(&entries, &OutputFormat { renumber_start: 1, ..OutputFormat::default() });
+    let reparsed = parse_srt(&output)?;
+    if reparsed.len() != entries.len() || reparsed.iter().zip(&entries).any(|(a, b)| a.text != b.text) {
+        bail!("Sample round-trip failed: processed {} entries but the output parses differently", entries.len());
+    }
+    print_success(&format!("Sample round-trip: {} entries in, {} out, {} rule events", parsed, entries.len(), audit.len()));
+    
+    Ok(())
+}
+
+/// Rewrites a loosely written timing line (`0:1:2.5->00:00:04:000`, `-- >`, `;` or `:`
+/// before the milliseconds, short millisecond fields) as a canonical `start --> end` line
+fn repair_timing_line(line: &str) -> Option<String> {
+    static TIMING: OnceLock<Regex> = OnceLock::new();
+    let timing = TIMING.get_or_init(|| Regex::new(
+        r"^\s*(\d+)\s*:\s*(\d{1,2})\s*:\s*(\d{1,2})\s*[,.:;]\s*(\d{1,3})\s*(?:-+|–|—)\s*>\s*(\d+)\s*:\s*(\d{1,2})\s*:\s*(\d{1,2})\s*[,.:;]\s*(\d{1,3})(.*)$"
+    ).unwrap());
+    
+    let caps = timing.captures(line)?;
+    let timestamp = |first: usize| -> Option<Timestamp> {
+        // A short millisecond field is a decimal fraction, so `,5` means 500ms
+        let millis = format!("{:0<3}", &caps[first + 3]);
+        Timestamp::parse(&format!("{}:{}:{},{}", &caps[first], &caps[first + 1], &caps[first + 2], millis))
+    };
+    let range = TimeRange { start: timestamp(1)?, end: timestamp(5)? };
+    let rest = caps[9].trim();
+    
+    Some(if rest.is_empty() { range.to_string() } else { format!("{} {}", range, rest) })
+}
+
+/// Normalizes line endings, BOM, blank lines and timing lines so the content parses as
+/// standard SRT. Returns the repaired content and how many timing lines were rewritten.
+fn repair_srt_structure(content: &str) -> (String, usize) {
+    let content = content.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+    let mut repaired = String::with_capacity(content.len());
+    let mut rewritten = 0;
+    let mut previous_blank = true;
+    
+    for line in content.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            // Runs of blank lines would otherwise parse as empty blocks
+            if !previous_blank {
+                repaired.push('\n');
+            }
+            previous_blank = true;
+            continue;
+        }
+        match repair_timing_line(line) {
+            Some(fixed) => {
+                if fixed != line {
+                    rewritten += 1;
+                }
+                repaired.push_str(&fixed);
+            }
+            None => repaired.push_str(line),
+        }
+        repaired.push('\n');
+        previous_blank = false;
+    }
+    
+    (repaired, rewritten)
+}
+
+/// Structural repair only: no SKIP/COMBINE/END rules or text cleanup are applied
+fn repair_srt_file(input: &Path, output: &Option<PathBuf>, format: &OutputFormat) -> Result<()> {
+    let content = read_input_file(input)?;
+    let (repaired, rewritten) = repair_srt_structure(&content);
+    let entries = parse_srt(&repaired)?;
+    
+    if entries.is_empty() {
+        warn_empty_input(input, content.trim().is_empty());
+        return Ok(());
+    }
+    
+    let unparsed = entries.iter().filter(|e| repair_timing_line(&e.timestamp).is_none()).count();
+    if unparsed > 0 {
+        print_warning(&format!("{} entries still have a timing line that could not be repaired", unparsed));
+    }
+    
+    let output_text = format_srt_output(&entries, format);
+    match output {
+        Some(path) => write_output_file(path, &output_text)?,
+        // Repairing in place is the point of the command, so no --force is needed
+        None => fs::write(input, &output_text)
+            .with_context(|| format!("Failed to write repaired file: {}", input.display()))?,
+    }
+    
+    print_info(&format!("Rewrote {} timing lines", rewritten));
+    print_success(&format!("Repaired {} entries and saved to: {}", entries.len(), output.as_deref().unwrap_or(input).display()));
+    
+    Ok(())
+}
+
+/// One `  KIND: count` line per rule type in `config`
+fn format_rule_counts(config: &Config) -> String {
+    [
+        ("SKIP", config.skip_words.len()),
+        ("COMBINE", config.combine_phrases.len()),
+        ("COMBINE_EQUAL", config.combine_equal.len()),
+        ("END", config.end_words.len()),
+        ("INSERT", config.insert_phrases.len()),
+        ("SPLIT", config.split_words.len()),
+    ].iter()
+        .map(|(kind, count)| format!("  {}: {}\n", kind, count))
+        .collect()
+}
+
+/// Starter config printed by `init-config`; every applied directive is shown once in the
+/// quoted-list format `Config::from_content` reads
+const SAMPLE_CONFIG: &str = r#"# srt-handle config
+#
+# Each directive takes a comma-separated list of double-quoted entries on a single
+# line. Matching ignores case. Lines starting with # are comments. A directive that
+# appears twice keeps only its last line; leave one out to disable that rule.
+
+# SKIP: drop every cue whose text contains one of these words
+SKIP: "applause", "music", "laughter"
+
+# COMBINE: join a cue ending with the first word to the next cue when it starts
+# with the second word, e.g. "thank" / "you", "drop" / "out"
+COMBINE: "thank you", "entire life", "drop out"
+
+# COMBINE_EQUAL: collapse consecutive cues that are exactly one of these phrases
+# (stutters such as "Wait" / "wait") into a single cue
+COMBINE_EQUAL: "no", "wait"
+
+# END: move these words from the end of a cue to the start of the next one, so
+# lines don't end on a dangling word
+END: "I", "my", "to", "the", "in the", "and"
+
+# INSERT and SPLIT lines are read so older configs keep loading, but this version
+# does not apply them yet; uncommenting these lines changes nothing.
+# INSERT: "very much"
+# SPLIT: "I", "my", "so"
+"#;
+
+/// Splits CSV content into records of fields (RFC 4180: quoted fields may hold commas,
+/// doubled quotes and line breaks)
+fn parse_csv_records(content: &str) -> Result<Vec<Vec<String>>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = content.chars().peekable();
+    let mut quoted = false;
+    
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            _ if quoted => field.push(c),
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        bail!("CSV ends inside a quoted field");
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    
+    Ok(records)
+}
+
+/// Reads an imported time given either as `HH:MM:SS,mmm` (or `.mmm`) or as seconds (`12.5`)
+fn parse_import_time(value: &str) -> Result<Timestamp> {
+    let value = value.trim();
+    if value.contains(':') {
+        return Timestamp::parse(value).context("expected HH:MM:SS,mmm or a number of seconds");
+    }
+    let seconds = parse_decimal(value).ok().filter(|s| s.is_finite() && *s >= 0.0)
+        .context("expected HH:MM:SS,mmm or a number of seconds")?;
+    Timestamp::checked_from_millis((seconds * 1000.0).round() as u64)
+}
+
+/// Builds entries from CSV rows with `start`, `end` and `text` columns (found by header
+/// name, in any order; other columns are ignored)
+fn parse_csv_import(content: &str) -> Result<Vec<SrtEntry>> {
+    let mut records = parse_csv_records(content.trim_start_matches('\u{feff}'))?.into_iter();
+    let header: Vec<String> = records.next().unwrap_or_default()
+        .iter().map(|name| name.trim().to_lowercase()).collect();
+    
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let (Some(start_col), Some(end_col), Some(text_col)) = (column("start"), column("end"), column("text")) else {
+        let missing: Vec<&str> = ["start", "end", "text"].into_iter().filter(|name| column(name).is_none()).collect();
+        bail!("CSV is missing required column(s): {} (header has: {})", missing.join(", "), header.join(", "));
+    };
+    
+    let mut entries = Vec::new();
+    for (row, record) in records.enumerate() {
+        // Row 1 is the header
+        let row = row + 2;
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        let field = |col: usize| record.get(col).map(String::as_str).unwrap_or("");
+        
+        let start = parse_import_time(field(start_col))
+            .with_context(|| format!("Row {}: invalid start time '{}'", row, field(start_col)))?;
+        let end = parse_import_time(field(end_col))
+            .with_context(|| format!("Row {}: invalid end time '{}'", row, field(end_col)))?;
+        if end < start {
+            bail!("Row {}: end time {} is before start time {}", row, end, start);
+        }
+        
+        entries.push(SrtEntry {
+            index: entries.len() as u32 + 1,
+            timestamp: TimeRange { start, end }.to_string(),
+            text: field(text_col).trim().to_string(),
+            position_tag: None,
+            speaker: None,
+            notes: Vec::new(),
+        });
+    }
+    
+    Ok(entries)
+}
+
+fn import_srt_file(input: &Path, format: ImportFormat, output: &Option<PathBuf>, output_format: &OutputFormat) -> Result<()> {
+    let content = read_input_file(input)?;
+    
+    let entries = match format {
+        ImportFormat::Csv => parse_csv_import(&content)?,
+    };
+    
+    if entries.is_empty() {
+        print_warning(&format!("{} has no data rows, no output written", input.display()));
+        return Ok(());
+    }
+    
+    let output_path = output.clone().unwrap_or_else(|| input.with_extension("srt"));
+    
+    write_output_file(&output_path, format_srt_output(&entries, output_format))?;
+    
+    print_success(&format!("Imported {} entries to: {}", entries.len(), output_path.display()));
+    
+    Ok(())
+}
+
+/// File name part for a speaker: lowercase letters and digits, other runs become `-`
+fn speaker_slug(speaker: &str) -> String {
+    let slug: String = speaker.chars()
+        .map(|c| if c.is_alphanumeric() { c.to_lowercase().next().unwrap_or(c) } else { '-' })
+        .collect();
+    slug.split('-').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("-")
+}
+
+/// Writes `<stem>_<speaker>.srt` for every speaker, keeping the original timing. The speaker
+/// comes from the metadata line, or else from a label at the start of the cue, which is
+/// removed from the text. Cues without either go to `<stem>_unassigned.srt`.
+fn split_srt_by_speaker(input: &Path, out_dir: &Option<PathBuf>, label_pattern: &str, format: &OutputFormat) -> Result<()> {
+    let pattern = Regex::new(label_pattern)
+        .with_context(|| format!("Invalid --speaker-label-pattern: {}", label_pattern))?;
+    let content = read_input_file(input)?;
+    let entries = parse_srt(&content)?;
+    
+    if entries.is_empty() {
+        warn_empty_input(input, content.trim().is_empty());
+        return Ok(());
+    }
+    
+    let mut speakers: BTreeMap<String, Vec<SrtEntry>> = BTreeMap::new();
+    for entry in entries {
+        let (slug, text) = match (&entry.speaker, split_speaker_label(&entry.text, &pattern)) {
+            (Some(speaker), _) => (speaker_slug(speaker), entry.text.clone()),
+            (None, Some((label, rest))) => (speaker_slug(label), rest.to_string()),
+            (None, None) => (String::new(), entry.text.clone()),
+        };
+        let slug = if slug.is_empty() { "unassigned".to_string() } else { slug };
+        speakers.entry(slug).or_default().push(SrtEntry { text, ..entry });
+    }
+    
+    let dir = match out_dir {
+        Some(dir) => {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+            dir.clone()
+        }
+        None => input.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    let stem = input.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    
+    for (slug, cues) in &speakers {
+        let path = dir.join(format!("{}_{}.srt", stem, slug));
+        write_output_file(&path, format_srt_output(cues, format))?;
+        print_info(&format!("{} ({} entries)", path.display(), cues.len()));
+    }
+    
+    print_success(&format!("Split {} into {} speaker file(s)", input.display(), speakers.len()));
+    
+    Ok(())
+}
+
+fn process_single_file(input: &Path, output: &Option<PathBuf>, config_path: &Option<PathBuf>, options: &ProcessOptions) -> Result<()> {
+    let mut config = Config::resolve(config_path)?;
+    // Disabled rule types are emptied so every pass simply finds nothing to do
+    let enabled = |kind| if options.only.is_empty() { !options.skip_rule.contains(&kind) } else { options.only.contains(&kind) };
+    if !enabled(RuleKind::Skip) {
+        config.skip_words.clear();
+    }
+    if !enabled(RuleKind::Combine) {
+        config.combine_phrases.clear();
+        config.combine_equal.clear();
+    }
+    if !enabled(RuleKind::End) {
+        config.end_words.clear();
+    }
+    let mut matcher: Box<dyn Matcher> = if options.fuzzy {
+        Box::new(FuzzyMatcher { max_distance: options.fuzzy_distance, locale: options.locale })
+    } else {
+        Box::new(DefaultMatcher { locale: options.locale })
+    };
+    if options.match_ignore_tags {
+        matcher = Box::new(TagIgnoringMatcher { inner: matcher });
+    }
+    
+    // parse, skip, timing, combine, end, write
+    let progress = |step: &str, done: usize| emit_progress(options.progress_json, "process", step, done, 6);
+    
+    let speaker_label = match options.strip_speaker_labels {
+        true => Some(Regex::new(&options.speaker_label_pattern)
+            .with_context(|| format!("Invalid --speaker-label-pattern: {}", options.speaker_label_pattern))?),
+        false => None,
+    };
+    
+    let output_path = match (output, &options.output_template) {
+        (Some(output), _) => output.clone(),
+        (None, Some(template)) => {
+            // Only read the input up front when the template needs its language
+            let lang = if template.contains("{lang}") {
+                let codes: Vec<&str> = detect_languages(&read_input_file(input)?).iter().map(|(code, _)| *code).collect();
+                if codes.is_empty() { "und".to_string() } else { codes.join("-") }
+            } else {
+                String::new()
+            };
+            expand_output_template(template, input, &lang, 1)?
+        }
+        (None, None) => {
+            let mut path = input.to_path_buf();
+            if let Some(stem) = path.file_stem() {
+                let new_name = format!("{}_ok.srt", stem.to_string_lossy());
+                path.set_file_name(new_name);
+            }
+            path
+        }
+    };
+    
+    if options.stream {
+        match streaming_blocker(input, &config, options)? {
+            None => {
+                let Some((repaired, dropped_short)) = stream_process_file(input, &output_path, &config, matcher.as_ref(), speaker_label.as_ref(), options)? else {
+                    return Ok(());
+                };
+                progress("write", 6);
+                if options.fix_mojibake {
+                    print_info(&format!("Repaired mojibake in {} entries", repaired));
+                }
+                print_success(&format!("Processed SRT file saved to: {}", output_path.display()));
+                if let Some(min_ms) = options.skip_shorter_than {
+                    print_info(&format!("Dropped {} entries shorter than {}ms", dropped_short, min_ms));
+                }
+                return Ok(());
+            }
+            Some(reason) => print_warning(&format!("--stream cannot be combined with {}; processing the whole file in memory", reason)),
+        }
+    }
+    
+    let content = read_input_file(input)?;
+    
+    let mut entries = if options.lyrics {
+        parse_srt_lyrics(&content)?
+    } else {
+        parse_srt(&content)?
+    };
+    progress("parse", 1);
+    
+    if entries.is_empty() {
+        warn_empty_input(input, content.trim().is_empty());
+        return Ok(());
+    }
+    print_log(LogLevel::Debug, &format!("Parsed {} entries from {}; rules: {} SKIP, {} COMBINE, {} COMBINE_EQUAL, {} END",
+        entries.len(), input.display(), config.skip_words.len(), config.combine_phrases.len(), config.combine_equal.len(), config.end_words.len()));
+    
+    let original = options.diff.then(|| entries.clone());
+    
+    // COMBINE and END rules assume one language per cue, so they are skipped for merged pairs
+    let mut bilingual = false;
+    if options.detect_bilingual && looks_bilingual(&entries, options.timestamp_tolerance_ms) {
+        let before = entries.len();
+        let pairing = MergeOptions { separator: "\n".to_string(), pair_by: PairBy::Timestamp, timestamp_tolerance_ms: options.timestamp_tolerance_ms, dedup_on_merge: false };
+        entries = merge_entry_runs(&entries, 2, &pairing);
+        print_info(&format!("Detected a bilingual file; merged {} entries into {} and skipping COMBINE/END rules", before, entries.len()));
+        bilingual = true;
+    }
+    
+    let repaired = apply_text_cleanup(&mut entries, options, speaker_label.as_ref());
+    if options.fix_mojibake {
+        print_info(&format!("Repaired mojibake in {} entries", repaired));
+    }
+    
+    if options.check {
+        print!("{}", format_skip_check(&entries, &config.skip_words, matcher.as_ref()));
+        return Ok(());
+    }
+    
+    let mut audit = Vec::new();
+    
+    // SKIP words that matched something, for --report-unused
+    let mut skip_hits = BTreeSet::new();
+    let mut matches_skip = |text: &str| match find_skip_word(text, &config.skip_words, matcher.as_ref()) {
+        Some(word) => {
+            skip_hits.insert(word.clone());
+            true
+        }
+        None => false,
+    };
+    
+    entries.retain_mut(|entry| {
+        let lines_before = entry.text.lines().count();
+        let keep = apply_skip(entry, options.skip_lines, options.invert_skip, &mut matches_skip);
+        if !keep {
+            audit.push(AuditRecord { index: entry.index, pass: "skip", detail: "skipped".to_string() });
+        } else if entry.text.lines().count() < lines_before {
+            audit.push(AuditRecord { index: entry.index, pass: "skip", detail: format!("removed {} line(s)", lines_before - entry.text.lines().count()) });
+        }
+        keep
+    });
+    
+    progress("skip", 2);
+    
+    let mut dropped_short = 0;
+    if let Some(min_ms) = options.skip_shorter_than {
+        let before = entries.len();
+        entries.retain(|entry| {
+            let keep = !is_shorter_than(entry, min_ms);
+            if !keep {
+                audit.push(AuditRecord { index: entry.index, pass: "skip-shorter-than", detail: format!("dropped, shorter than {}ms", min_ms) });
+            }
+            keep
+        });
+        dropped_short = before - entries.len();
+    }
+    
+    if options.dedup_overlap {
+        apply_overlap_dedup(&mut entries, options.overlap_min_words);
+    }
+    
+    if options.merge_same_speaker {
+        apply_speaker_merge(&mut entries, options.max_speaker_gap);
+    }
+    
+    if options.join_short {
+        apply_join_short(&mut entries, options.min_cue);
+    }
+    progress("timing", 3);
+    
+    let mut combine_equal_hits = vec![0; config.combine_equal.len()];
+    let mut combine_hits = vec![0; config.combine_phrases.len()];
+    let mut end_hits = vec![0; config.end_words.len()];
+    let add_hits = |total: &mut Vec<usize>, hits: Vec<usize>| total.iter_mut().zip(hits).for_each(|(total, hits)| *total += hits);
+    
+    // An END move can create a COMBINE match the single pass already went past
+    let passes = if bilingual { 0 } else if options.iterate { options.max_iterations.max(1) } else { 1 };
+    for pass in 1..=passes {
+        let before: Vec<(String, String)> = entries.iter().map(|e| (e.timestamp.clone(), e.text.clone())).collect();
+        
+        add_hits(&mut combine_equal_hits, apply_combine_equal(&mut entries, &config.combine_equal, &mut audit));
+        add_hits(&mut combine_hits, apply_combine_rules(&mut entries, &config.combine_phrases, &options.combine_options(), matcher.as_ref(), &mut audit));
+        add_hits(&mut end_hits, apply_end_rules(&mut entries, &config.end_words, options.end_as_split, options.redistribute_timing, matcher.as_ref(), &mut audit));
+        
+        if !options.iterate {
+            break;
+        }
+        let changed = entries.len() != before.len()
+            || entries.iter().zip(&before).any(|(e, (timestamp, text))| e.timestamp != *timestamp || e.text != *text);
+        if !changed {
+            print_log(LogLevel::Debug, &format!("COMBINE/END rules reached a fixed point after {} pass(es)", pass));
+            break;
+        }
+        if pass == passes {
+            print_warning(&format!("COMBINE/END rules still changed entries after --max-iterations {} passes", passes));
+        }
+    }
+    progress("combine", 4);
+    progress("end", 5);
+    
+    if options.autotime {
+        let (extended, too_fast) = apply_autotime(&mut entries, options.target_cps, options.min_cue, options.autotime_gap, &mut audit);
+        print_info(&format!("Extended {} cues for reading time; {} still read faster than {} cps or are shorter than {}ms", extended, too_fast, options.target_cps, options.min_cue));
+    }
+    
+    if let Some(max_ms) = options.max_cue_duration {
+        apply_max_cue_duration(&mut entries, max_ms, options.long_cue_split);
+    }
+    
+    if let Some(max_chars) = options.truncate_chars {
+        apply_truncation(&mut entries, max_chars, &options.ellipsis);
+    }
+    
+    // Cues split by END rules have index 0 and take the index of the cue they came from
+    if options.format.no_renumber {
+        let mut previous = 0;
+        for entry in entries.iter_mut() {
+            if entry.index == 0 {
+                entry.index = previous;
+            } else {
+                previous = entry.index;
+            }
+        }
+        
+        let unique: BTreeSet<u32> = entries.iter().map(|e| e.index).collect();
+        if unique.len() < entries.len() {
+            print_warning(&format!("{} entries share their original index with another entry (split cues keep the index they came from)", entries.len() - unique.len()));
+        }
+    }
+    
+    let output_content = format_srt_output(&entries, &options.format);
+    
+    if options.verify_roundtrip {
+        verify_roundtrip(&entries, &output_content)?;
+    }
+    
+    // Check both destinations first, so a clash on the audit log doesn't leave the output
+    // written without it
+    check_output_path(&output_path)?;
+    if let Some(audit_path) = &options.audit {
+        check_output_path(audit_path)?;
+    }
+    
+    if options.strict_srt {
+        let violations = spec_violations(&entries, options);
+        if !violations.is_empty() {
+            for (position, problems) in &violations {
+                println!("{} {}", options.format.output_index(*position, &entries[*position]), problems.join(", "));
+            }
+            bail!("{} cue(s) break the --strict-srt limits; output not written", violations.len());
+        }
+    }
+    
+    if let Some(original) = &original {
+        print!("{}", unified_cue_diff(original, &entries, input, &output_path, &options.format));
+        return Ok(());
+    }
+    
+    write_output_file(&output_path, output_content)?;
+    progress("write", 6);
+    
+    for record in &audit {
+        print_log(LogLevel::Trace, &format!("{} {}: {}", record.index, record.pass, record.detail));
+    }
+    
+    if let Some(audit_path) = &options.audit {
+        // Stable sort keeps each entry's events in pass order
+        audit.sort_by_key(|record| record.index);
+        let log: String = audit.iter()
+            .map(|record| format!("{}\t{}\t{}\n", record.index, record.pass, record.detail))
+            .collect();
+        write_output_file(audit_path, log)?;
+        print_info(&format!("Wrote {} audit records to: {}", audit.len(), audit_path.display()));
+    }
+    
+    print_success(&format!("Processed SRT file saved to: {}", output_path.display()));
+    
+    if let Some(min_ms) = options.skip_shorter_than {
+        print_info(&format!("Dropped {} entries shorter than {}ms", dropped_short, min_ms));
+    }
+    
+    if options.speed_report {
+        print!("{}", format_speed_report(&entries, options.min_cue, &options.format));
+    }
+    
+    if options.report_unused {
+        let unused = unused_rules(&config, &skip_hits, &combine_hits, &combine_equal_hits, &end_hits);
+        if unused.is_empty() {
+            println!("Every SKIP, COMBINE, COMBINE_EQUAL and END rule matched at least once");
+        } else {
+            println!("{} rule(s) never matched:", unused.len());
+            for rule in &unused {
+                println!("  {}", rule);
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+fn main() {
+    let args = Args::parse();
+    begin_run(RunSettings::from_args(&args));
+    
+    if let Err(e) = run(args).and_then(|_| check_strict()) {
+        print_error(&format!("Error: {:?}", e));
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    match args.command {
+        Commands::Process { input, output, config, options } => {
+            process_single_file(&input, &output, &config, &options)?;
+        }
+        Commands::Batch { dir, config, options } => {
+            batch_process_srt_files(&dir, &config, &options)?;
+        }
+        Commands::Merge { input, output, lines, grouped, mut options, format } => {
+            // Allow a literal \n on the command line to mean a line break
+            options.separator = options.separator.replace("\\n", "\n");
+            merge_bilingual_srt(&input, &output, lines, grouped, &options, &format)?;
+        }
+        Commands::Validate { input, expect_min_entries, expect_max_cps, expect_max_cue } => {
+            validate_srt_file(&input, expect_min_entries, expect_max_cps, expect_max_cue)?;
+        }
+        Commands::Diff { old, new, text_only, timestamp_tolerance_ms } => {
+            diff_srt_files(&old, &new, text_only, timestamp_tolerance_ms)?;
+        }
+        Commands::FromFrames { input, output, fps, drop_frame, format } => {
+            convert_frames_to_srt(&input, &output, fps, drop_frame, &format)?;
+        }
+        Commands::Stats { input, fps, cps_outliers } => {
+            print_srt_stats(&input, fps, cps_outliers)?;
+        }
+        Commands::Head { input, count } => {
+            print_entry_slice(&input, count, false)?;
+        }
+        Commands::Tail { input, count } => {
+            print_entry_slice(&input, count, true)?;
+        }
+        Commands::Export { input, output, format } => {
+            export_srt_file(&input, &output, format)?;
+        }
+        Commands::ScanLanguages { dir, json } => {
+            scan_languages(&dir, json)?;
+        }
+        Commands::Rebase { input, output, format } => {
+            rebase_srt_file(&input, &output, &format)?;
+        }
+        Commands::Interleave { first, second, output, mut options, format } => {
+            options.separator = options.separator.replace("\\n", "\n");
+            interleave_srt_files(&first, &second, &output, &options, &format)?;
+        }
+        Commands::Resegment { input, by, output, format } => {
+            resegment_srt_file(&input, by, &output, &format)?;
+        }
+        Commands::Scale { input, output, from_fps, to_fps, assume_fps, format } => {
+            let from_fps = match (from_fps, assume_fps) {
+                (Some(fps), _) => fps,
+                (None, true) => match fps_from_file_name(&input) {
+                    Some(fps) => {
+                        print_info(&format!("Assuming {} fps from the file name", fps));
+                        fps
+                    }
+                    None => bail!("Could not infer a frame rate from {}; pass --from-fps", input.display()),
+                },
+                (None, false) => bail!("Pass --from-fps, or --assume-fps to read it from the file name"),
+            };
+            scale_srt_file(&input, &output, from_fps, to_fps, &format)?;
+        }
+        Commands::Shift { input, by, output, format } => {
+            shift_srt_file(&input, by, &output, &format)?;
+        }
+        Commands::Segment { input, min_gap, out_dir, format } => {
+            segment_srt_file(&input, min_gap, &out_dir, &format)?;
+        }
+        Commands::Doctor => {
+            run_doctor()?;
+        }
+        Commands::Repair { input, output, format } => {
+            repair_srt_file(&input, &output, &format)?;
+        }
+        Commands::InitConfig => {
+            print!("{}", SAMPLE_CONFIG);
+        }
+        Commands::Import { input, format, output, output_format } => {
+            import_srt_file(&input, format, &output, &output_format)?;
+        }
+        Commands::SplitBySpeaker { input, out_dir, speaker_label_pattern, format } => {
+            split_srt_by_speaker(&input, &out_dir, &speaker_label_pattern, &format)?;
+        }
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// SRT text with one cue per second, 900ms long each
+    fn srt_indexed(cues: &[(u32, &str)]) -> String {
+        cues.iter().enumerate()
+            .map(|(i, (index, text))| format!("{}\n00:00:{:02},000 --> 00:00:{:02},900\n{}\n\n", index, i, i, text))
+            .collect()
+    }
+    
+    /// A fresh empty directory under the system temp dir, unique to the test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("srt-handle-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+    
+    fn quiet(strict: bool) -> RunSettings {
+        RunSettings { strict, log_level: LogLevel::Warn, ..RunSettings::default() }
+    }
+    
+    /// A zip archive holding `files` uncompressed (method 0, "stored")
+    fn stored_zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let (mut zip, mut central) = (Vec::new(), Vec::new());
+        for (name, content) in files {
+            let offset = zip.len() as u32;
+            // version 20, no flags, stored, 1980-01-01 00:00, crc, sizes, name length
+            let fields = |zip: &mut Vec<u8>| {
+                zip.extend(20u16.to_le_bytes());
+                zip.extend([0u8; 4]);
+                zip.extend(0u16.to_le_bytes());
+                zip.extend(0x21u16.to_le_bytes());
+                zip.extend(crc32(content.as_bytes()).to_le_bytes());
+                zip.extend((content.len() as u32).to_le_bytes());
+                zip.extend((content.len() as u32).to_le_bytes());
+                zip.extend((name.len() as u16).to_le_bytes());
+                zip.extend(0u16.to_le_bytes());
+            };
+            zip.extend(0x0403_4b50u32.to_le_bytes());
+            fields(&mut zip);
+            zip.extend(name.as_bytes());
+            zip.extend(content.as_bytes());
             
-            if words.len() >= end_word_parts.len() {
-                let last_words = &words[words.len() - end_word_parts.len()..];
-                
-                if last_words.iter().zip(end_word_parts.iter())
-                    .all(|(a, b)| a.to_lowercase() == b.to_lowercase()) {
-                    
-                    let remaining_words = &words[..words.len() - end_word_parts.len()];
-                    entries[i].text = remaining_words.join(" ");
-                    
-                    entries[i + 1].text = format!("{} {}", end_word, entries[i + 1].text);
-                    break;
+            central.extend(0x0201_4b50u32.to_le_bytes());
+            central.extend(20u16.to_le_bytes());
+            fields(&mut central);
+            // comment length, disk number and file attributes
+            central.extend([0u8; 10]);
+            central.extend(offset.to_le_bytes());
+            central.extend(name.as_bytes());
+        }
+        
+        let central_offset = zip.len() as u32;
+        zip.extend(&central);
+        zip.extend(0x0605_4b50u32.to_le_bytes());
+        zip.extend([0u8; 4]);
+        zip.extend((files.len() as u16).to_le_bytes());
+        zip.extend((files.len() as u16).to_le_bytes());
+        zip.extend((central.len() as u32).to_le_bytes());
+        zip.extend(central_offset.to_le_bytes());
+        zip.extend(0u16.to_le_bytes());
+        zip
+    }
+    
+    /// Runs a command line as main would, starting a fresh run
+    fn run_cli(args: &[&str]) -> Result<()> {
+        begin_run(RunSettings { force: true, ..quiet(false) });
+        run(Args::try_parse_from(["srt-handle"].iter().chain(args))?)
+    }
+    
+    fn batch_options(args: &[&str]) -> BatchOptions {
+        let args = Args::try_parse_from(["srt-handle", "batch"].iter().chain(args)).unwrap();
+        let Commands::Batch { options, .. } = args.command else { unreachable!() };
+        options
+    }
+    
+    /// Writes one small SRT file per name into `dir`
+    fn write_srt_files(dir: &Path, names: &[&str]) {
+        for name in names {
+            fs::write(dir.join(name), srt_indexed(&[(1, name)])).unwrap();
+        }
+    }
+    
+    /// A `process` command line reading `input` and `config` from a fresh directory and writing out.srt there
+    fn process_args(name: &str, input: &str, config: &str) -> (PathBuf, Vec<String>) {
+        let dir = temp_dir(name);
+        fs::write(dir.join("in.srt"), input).unwrap();
+        fs::write(dir.join("config.txt"), config).unwrap();
+        let args = ["process", "in.srt", "-o", "out.srt", "-c", "config.txt"].iter()
+            .map(|arg| match *arg {
+                "in.srt" | "out.srt" | "config.txt" => dir.join(arg).to_string_lossy().into_owned(),
+                arg => arg.to_string(),
+            })
+            .collect();
+        (dir, args)
+    }
+    
+    /// Runs `process` with `args` over `input` and `config` and returns what it wrote
+    fn process_srt(name: &str, input: &str, config: &str, args: &[&str]) -> Result<String> {
+        let (dir, mut cli) = process_args(name, input, config);
+        cli.extend(args.iter().map(|arg| arg.to_string()));
+        run_cli(&cli.iter().map(String::as_str).collect::<Vec<_>>())?;
+        Ok(fs::read_to_string(dir.join("out.srt"))?)
+    }
+    
+    /// SRT text with the cues numbered from 1 and timed in milliseconds
+    fn srt_timed(cues: &[(u64, u64, &str)]) -> String {
+        cues.iter().enumerate()
+            .map(|(i, (start, end, text))| format!("{}\n{}\n{}\n\n", i + 1, TimeRange {
+                start: Timestamp::from_millis(*start),
+                end: Timestamp::from_millis(*end),
+            }, text))
+            .collect()
+    }
+    
+    fn texts(entries: &[SrtEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.text.as_str()).collect()
+    }
+    
+    fn timestamps(entries: &[SrtEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.timestamp.as_str()).collect()
+    }
+    
+    #[test]
+    fn swapped_index_and_timestamp_lines_still_parse() {
+        let entries = parse_srt("1\n00:00:01,000 --> 00:00:02,000\nHello\n\n00:00:03,000 --> 00:00:04,000\n2\nWorld\n").unwrap();
+        assert_eq!(texts(&entries), ["Hello", "World"]);
+        assert_eq!(entries[1].index, 2);
+        assert_eq!(entries[1].timestamp, "00:00:03,000 --> 00:00:04,000");
+    }
+    
+    #[test]
+    fn renumber_start_sets_the_first_index() {
+        let entries = parse_srt(&srt_indexed(&[(1, "a"), (2, "b")])).unwrap();
+        let output = format_srt_output(&entries, &OutputFormat { renumber_start: 41, ..OutputFormat::default() });
+        assert!(output.starts_with("41\n"), "{}", output);
+        assert_eq!(parse_srt(&output).unwrap().iter().map(|e| e.index).collect::<Vec<_>>(), [41, 42]);
+    }
+    
+    #[test]
+    fn check_reports_skip_matches_without_writing() {
+        let input = srt_indexed(&[(1, "hello"), (2, "[Music] playing"), (3, "bye")]);
+        let report = format_skip_check(&parse_srt(&input).unwrap(), &["music".to_string()], &DefaultMatcher::default());
+        assert_eq!(report, "2 [music] [Music] playing\nSKIP rules would remove 1 of 3 entries\n");
+        
+        let (dir, mut cli) = process_args("check", &input, "SKIP: \"music\"\n");
+        cli.push("--check".to_string());
+        run_cli(&cli.iter().map(String::as_str).collect::<Vec<_>>()).unwrap();
+        assert!(!dir.join("out.srt").exists());
+    }
+    
+    #[test]
+    fn truncation_cuts_at_a_word_boundary_and_leaves_short_entries_alone() {
+        assert_eq!(truncate_text("the quick brown fox", 12, "…"), "the quick…");
+        assert_eq!(truncate_text("the quick brown fox", 12, "..."), "the quick...");
+        assert_eq!(truncate_text("short", 12, "…"), "short");
+        
+        let output = process_srt("truncate", &srt_indexed(&[(1, "the quick brown fox"), (2, "short")]), "", &["--truncate-chars", "12"]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["the quick…", "short"]);
+    }
+    
+    #[test]
+    fn invert_skip_keeps_only_matching_entries() {
+        let input = srt_indexed(&[(1, "hello"), (2, "[music]"), (3, "bye"), (4, "more music")]);
+        let output = process_srt("invert-skip", &input, "SKIP: \"music\"\n", &["--invert-skip"]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["[music]", "more music"]);
+    }
+    
+    #[test]
+    fn timestamps_round_trip_through_millis() {
+        for text in ["00:00:00,000", "00:00:59,999", "00:59:59,999", "01:00:00,000", "12:34:56,789", "99:59:59,999"] {
+            let timestamp = Timestamp::parse(text).unwrap();
+            assert_eq!(Timestamp::from_millis(timestamp.to_millis()), timestamp, "{}", text);
+            assert_eq!(Timestamp::from_millis(timestamp.to_millis()).to_string(), text);
+        }
+        assert_eq!(Timestamp::parse("01:00:00,000").unwrap().to_millis(), 3_600_000);
+        assert_eq!(TimeRange::parse("00:59:59,500 --> 01:00:01,000").unwrap().duration_ms(), 1500);
+    }
+    
+    #[test]
+    fn end_as_split_cuts_the_cue_instead_of_moving_words() {
+        let input = "1\n00:00:01,000 --> 00:00:02,000\nWe left and\n\n2\n00:00:03,000 --> 00:00:06,000\nthen it rained and we ran\n";
+        let words = ["and".to_string()];
+        
+        let mut moved = parse_srt(input).unwrap();
+        apply_end_rules(&mut moved, &words, false, false, &DefaultMatcher::default(), &mut Vec::new());
+        assert_eq!(texts(&moved), ["We left", "and then it rained and we ran"]);
+        
+        let mut split = parse_srt(input).unwrap();
+        apply_end_rules(&mut split, &words, true, false, &DefaultMatcher::default(), &mut Vec::new());
+        assert_eq!(texts(&split), ["We left and", "then it rained and", "we ran"]);
+        assert_eq!(split[1].timestamp.split(" --> ").next(), Some("00:00:03,000"));
+        assert_eq!(split[2].timestamp.split(" --> ").nth(1), Some("00:00:06,000"));
+        
+        let mut lines = parse_srt(&srt_indexed(&[(1, "We ran and hid\nin the barn"), (2, "I came home and\nwe ate\n我回家了")])).unwrap();
+        apply_end_rules(&mut lines, &words, true, false, &DefaultMatcher::default(), &mut Vec::new());
+        assert_eq!(texts(&lines), ["We ran and", "hid\nin the barn", "I came home and", "we ate\n我回家了"]);
+    }
+    
+    #[test]
+    fn empty_and_whitespace_only_inputs_warn_instead_of_writing() {
+        for (name, input) in [("empty", ""), ("whitespace", "  \n\n\t\n")] {
+            for stream in [false, true] {
+                let (dir, mut cli) = process_args(&format!("empty-input-{}-{}", name, stream), input, "");
+                if stream {
+                    cli.push("--stream".to_string());
                 }
+                run_cli(&cli.iter().map(String::as_str).collect::<Vec<_>>()).unwrap();
+                assert!(!dir.join("out.srt").exists(), "{} stream={}", name, stream);
+                // What --strict turns into a failing exit
+                assert!(has_reported_problems(), "{} stream={}", name, stream);
             }
         }
-        i += 1;
     }
-}
-
-fn format_srt_output(entries: &[SrtEntry]) -> String {
-    entries.iter()
-        .enumerate()
-        .map(|(i, entry)| {
-            format!("{}\n{}\n{}\n", i + 1, entry.timestamp, entry.text)
-        })
-        .collect::<Vec<_>>()
-        .join("\n")
-}
-
-fn merge_bilingual_srt(input: &PathBuf, output: &Option<PathBuf>) -> Result<()> {
-    println!("Merging bilingual SRT file: {}", input.display());
     
-    let content = fs::read_to_string(input)
-        .with_context(|| format!("Failed to read input file: {}", input.display()))?;
+    #[test]
+    fn join_short_merges_forward_and_a_short_last_cue_backward() {
+        let input = srt_timed(&[(0, 300, "Hi"), (1000, 2000, "there"), (3000, 4000, "the end"), (4500, 4700, "!")]);
+        let entries = parse_srt(&process_srt("join-short", &input, "", &["--join-short", "--min-cue", "700ms"]).unwrap()).unwrap();
+        assert_eq!(texts(&entries), ["Hi there", "the end !"]);
+        assert_eq!(timestamps(&entries), ["00:00:00,000 --> 00:00:02,000", "00:00:03,000 --> 00:00:04,700"]);
+        
+        let mut entries = parse_srt(&srt_timed(&[(0, 300, "Hi\n你好"), (1000, 2000, "b")])).unwrap();
+        apply_join_short(&mut entries, 700);
+        assert_eq!(texts(&entries), ["Hi\n你好\nb"]);
+    }
+    
+    #[test]
+    fn validate_reports_every_position_of_a_duplicate_index() {
+        let entries = parse_srt(&srt_indexed(&[(1, "a"), (2, "b"), (2, "c"), (3, "d"), (2, "e")])).unwrap();
+        assert_eq!(validation_problems(&entries, None, None, None), ["Duplicate index 2 at entries 2, 3, 5"]);
+        
+        let entries = parse_srt(&srt_indexed(&[(1, "a"), (2, "b")])).unwrap();
+        assert!(validation_problems(&entries, None, None, None).is_empty());
+    }
+    
+    #[test]
+    fn config_env_var_is_used_between_the_flag_and_the_embedded_config() {
+        let dir = temp_dir("config-env");
+        let (env_config, flag_config) = (dir.join("env.txt"), dir.join("flag.txt"));
+        fs::write(&env_config, "SKIP: \"from env\"\n").unwrap();
+        fs::write(&flag_config, "SKIP: \"from flag\"\n").unwrap();
+        
+        let config = Config::resolve_with_env(&None, Some(env_config.clone().into())).unwrap();
+        assert_eq!(config.skip_words, ["from env"]);
+        let config = Config::resolve_with_env(&Some(flag_config), Some(env_config.into())).unwrap();
+        assert_eq!(config.skip_words, ["from flag"]);
+        let config = Config::resolve_with_env(&None, None).unwrap();
+        assert_eq!(config, Config::from_embedded().unwrap());
+    }
+    
+    #[test]
+    fn text_only_diff_ignores_timing_changes() {
+        let old = parse_srt(&srt_timed(&[(1000, 2000, "Hello"), (3000, 4000, "World")])).unwrap();
+        let new = parse_srt(&srt_timed(&[(1500, 2500, "Hello"), (3000, 4000, "Word")])).unwrap();
+        
+        assert_eq!(entry_differences(&old, &new, true, 0), ["2 text:\n  - World\n  + Word"]);
+        assert_eq!(entry_differences(&old, &new, false, 0), [
+            "1 timing: 00:00:01,000 --> 00:00:02,000 => 00:00:01,500 --> 00:00:02,500",
+            "2 text:\n  - World\n  + Word",
+        ]);
+    }
+    
+    #[test]
+    fn color_follows_the_terminal_unless_overridden() {
+        assert_eq!(paint("Warning: x", YELLOW, true), "\x1b[33mWarning: x\x1b[0m");
+        assert_eq!(paint("Warning: x", YELLOW, false), "Warning: x");
+        
+        assert_eq!(color_override(true, true), Some(false));
+        assert_eq!(color_override(false, true), Some(true));
+        assert_eq!(color_override(false, false), None);
+    }
+    
+    #[test]
+    fn timestamp_format_presets_render_a_known_time() {
+        let range = TimeRange::parse("01:02:03,004 --> 01:02:05,600").unwrap();
+        assert_eq!(range.format(TimestampFormat::Srt), "01:02:03,004 --> 01:02:05,600");
+        assert_eq!(range.format(TimestampFormat::Vtt), "01:02:03.004 --> 01:02:05.600");
+        assert_eq!(range.format(TimestampFormat::Compact), "1:02:03,004 --> 1:02:05,600");
+        
+        let output = process_srt("timestamp-format", &srt_timed(&[(1000, 2000, "a")]), "", &["--timestamp-format", "vtt"]).unwrap();
+        assert_eq!(output.lines().nth(1), Some("00:00:01.000 --> 00:00:02.000"));
+    }
+    
+    #[test]
+    fn preamble_before_the_first_entry_is_skipped() {
+        let entries = parse_srt("Downloaded from example.org\nEnjoy!\n\n1\n00:00:01,000 --> 00:00:02,000\nHello\n").unwrap();
+        assert_eq!(texts(&entries), ["Hello"]);
+        assert_eq!(entries[0].index, 1);
+    }
+    
+    #[test]
+    fn combine_limit_stops_a_cue_absorbing_more_neighbours() {
+        let input = srt_indexed(&[(1, "a x"), (2, "y x"), (3, "y x"), (4, "y")]);
+        let phrases = [("x".to_string(), "y".to_string())];
+        let combine = |limit| {
+            let mut entries = parse_srt(&input).unwrap();
+            apply_combine_rules(&mut entries, &phrases, &CombineOptions { limit, ..CombineOptions::default() }, &DefaultMatcher::default(), &mut Vec::new());
+            entries
+        };
+        
+        assert_eq!(texts(&combine(None)), ["a x y x y x y"]);
+        assert_eq!(texts(&combine(Some(1))), ["a x y x", "y x y"]);
+        assert_eq!(texts(&combine(Some(2))), ["a x y x y x", "y"]);
+    }
+    
+    #[test]
+    fn cjk_characters_are_twice_as_wide_as_ascii() {
+        assert_eq!(display_width("hello"), 5);
+        assert_eq!(display_width("你好"), 4);
+        assert_eq!(display_width("你好 ok"), 7);
+        
+        assert_eq!(truncate_text("你好世界朋友们", 9, "…"), "你好世界…");
+    }
+    
+    #[test]
+    fn merge_separator_goes_between_the_languages() {
+        let dir = temp_dir("merge-separator");
+        let (input, output) = (dir.join("bil.srt"), dir.join("out.srt"));
+        fs::write(&input, srt_timed(&[(1000, 2000, "Hello"), (1000, 2000, "你好")])).unwrap();
+        
+        run_cli(&["merge", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "--separator", " / "]).unwrap();
+        assert_eq!(texts(&parse_srt(&fs::read_to_string(&output).unwrap()).unwrap()), ["Hello / 你好"]);
+        
+        run_cli(&["merge", input.to_str().unwrap(), "-o", output.to_str().unwrap()]).unwrap();
+        assert!(fs::read_to_string(&output).unwrap().contains("\nHello\n你好\n"));
+    }
+    
+    #[test]
+    fn frame_ranges_convert_to_timestamps() {
+        let dir = temp_dir("from-frames");
+        let (input, output) = (dir.join("frames.txt"), dir.join("out.srt"));
+        fs::write(&input, "0 25 Hello\n50 112 World\n").unwrap();
+        
+        run_cli(&["from-frames", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "--fps", "25"]).unwrap();
+        let entries = parse_srt(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(texts(&entries), ["Hello", "World"]);
+        assert_eq!(timestamps(&entries), ["00:00:00,000 --> 00:00:01,000", "00:00:02,000 --> 00:00:04,480"]);
+        
+        // Drop-frame label 00:01:00;02 is the 1800th real frame at 29.97 fps
+        assert_eq!(frame_to_millis(1802, exact_frame_rate(29.97), 2), 60_060);
+        assert_eq!(frame_to_millis(1800, exact_frame_rate(29.97), 0), 60_060);
+    }
+    
+    #[test]
+    fn batch_run_twice_leaves_the_folder_unchanged() {
+        let dir = temp_dir("batch-twice");
+        write_srt_files(&dir, &["show [Chinese (Simplified)].srt"]);
+        
+        let snapshot = || {
+            let mut files: Vec<(String, String)> = fs::read_dir(&dir).unwrap()
+                .map(|entry| entry.unwrap().path())
+                .map(|path| (path.file_name().unwrap().to_string_lossy().into_owned(), fs::read_to_string(&path).unwrap()))
+                .collect();
+            files.sort();
+            files
+        };
+        
+        run_cli(&["batch", "-d", dir.to_str().unwrap()]).unwrap();
+        let first = snapshot();
+        assert_eq!(first.len(), 2);
+        run_cli(&["batch", "-d", dir.to_str().unwrap()]).unwrap();
+        assert_eq!(snapshot(), first);
+    }
+    
+    #[test]
+    fn position_tag_survives_combine_and_rewrapping_unchanged() {
+        let input = srt_indexed(&[(1, "{\\an8}I want to thank"), (2, "{\\an8}you all")]);
+        let output = process_srt("position-tag-combine", &input, "COMBINE: \"thank you\"\n", &[]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["I want to thank you all"]);
+        assert_eq!(output.matches("{\\an8}").count(), 1, "{}", output);
+        assert!(output.contains("\n{\\an8}I want to thank you all\n"), "{}", output);
+        
+        let output = process_srt("position-tag-flatten", &srt_indexed(&[(1, "{\\an8}top\nline")]), "", &["--flatten"]).unwrap();
+        assert!(output.contains("\n{\\an8}top line\n"), "{}", output);
+    }
+    
+    #[test]
+    fn stats_report_span_and_coverage() {
+        let entries = parse_srt(&srt_timed(&[(10_000, 12_000, "a"), (11_000, 14_000, "b"), (20_000, 30_000, "c")])).unwrap();
+        let ranges: Vec<TimeRange> = entries.iter().filter_map(|e| TimeRange::parse(&e.timestamp)).collect();
+        assert_eq!(covered_millis(&ranges), 14_000);
+        
+        let stats = format_srt_stats(Path::new("x.srt"), &entries, &ranges, None, false);
+        assert!(stats.contains("Span: 00:00:10,000 --> 00:00:30,000 (00:00:20,000)"), "{}", stats);
+        assert!(stats.contains("Subtitled time: 00:00:14,000"), "{}", stats);
+        assert!(stats.contains("Coverage: 70.0%"), "{}", stats);
+        
+        let entries = parse_srt(&srt_indexed(&[(1, "abc"), (2, "你好")])).unwrap();
+        let ranges: Vec<TimeRange> = entries.iter().filter_map(|e| TimeRange::parse(&e.timestamp)).collect();
+        assert!(format_srt_stats(Path::new("x.srt"), &entries, &ranges, None, false).contains("Widest line: 4 columns"));
+    }
+    
+    #[test]
+    fn skip_shorter_than_drops_only_entries_below_the_threshold() {
+        let input = srt_timed(&[(0, 199, "blip"), (1000, 1200, "exactly"), (2000, 3000, "long")]);
+        let output = process_srt("skip-shorter", &input, "", &["--skip-shorter-than", "200ms"]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["exactly", "long"]);
+    }
+    
+    #[test]
+    fn skip_lines_removes_matching_lines_and_empties_drop_the_cue() {
+        let input = srt_indexed(&[(1, "[music]\nHello there"), (2, "[music]\n[MUSIC]"), (3, "bye")]);
+        let output = process_srt("skip-lines", &input, "SKIP: \"[music]\"\n", &["--skip-lines"]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["Hello there", "bye"]);
+        
+        let output = process_srt("skip-whole-cue", &input, "SKIP: \"[music]\"\n", &[]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["bye"]);
+    }
+    
+    #[test]
+    fn toml_config_matches_the_legacy_format() {
+        let legacy = Config::from_content("SKIP: \"music\", \"applause\"\nCOMBINE: \"thank you\", \"drop out\"\nEND: \"and\", \"in the\"\n").unwrap();
+        let toml = Config::from_toml("# same rules\nskip = [\"music\", 'applause']\ncombine = [[\"thank\", \"you\"], [\"drop\", \"out\"]]\nend = [\n  \"and\",\n  \"in the\",\n]\n").unwrap();
+        assert_eq!(toml, legacy);
+        assert_eq!(toml.combine_phrases[1], ("drop".to_string(), "out".to_string()));
+        
+        let dir = temp_dir("toml-config");
+        fs::write(dir.join("rules.toml"), "skip = [\"music\"]\n").unwrap();
+        assert_eq!(Config::from_file(&dir.join("rules.toml")).unwrap().skip_words, ["music"]);
+    }
+    
+    #[test]
+    fn strict_fails_a_run_that_warned() {
+        begin_run(quiet(true));
+        Config::from_content("BOGUS: \"x\"\n").unwrap();
+        let error = check_strict().unwrap_err().to_string();
+        assert!(error.contains("1 warning(s) treated as errors"), "{}", error);
+        
+        begin_run(quiet(false));
+        Config::from_content("BOGUS: \"x\"\n").unwrap();
+        assert!(check_strict().is_ok());
+    }
+    
+    #[test]
+    fn strict_passes_a_clean_run() {
+        begin_run(quiet(true));
+        Config::from_content("SKIP: \"music\"\n").unwrap();
+        assert!(check_strict().is_ok());
+    }
+    
+    #[test]
+    fn begin_run_forgets_earlier_problems() {
+        begin_run(quiet(true));
+        print_warning("left over");
+        begin_run(quiet(true));
+        assert!(!has_reported_problems());
+    }
+    
+    #[test]
+    fn head_and_tail_show_the_right_entries_with_their_own_indices() {
+        let entries = parse_srt(&srt_indexed(&[(1, "one"), (5, "five"), (9, "nine"), (9, "nine again")])).unwrap();
+        
+        let head = parse_srt(&format_entry_slice(&entries, 2, false)).unwrap();
+        assert_eq!(texts(&head), ["one", "five"]);
+        assert_eq!(head.iter().map(|e| e.index).collect::<Vec<_>>(), [1, 5]);
+        
+        let tail = parse_srt(&format_entry_slice(&entries, 2, true)).unwrap();
+        assert_eq!(texts(&tail), ["nine", "nine again"]);
+        assert_eq!(tail.iter().map(|e| e.index).collect::<Vec<_>>(), [9, 9]);
+        
+        assert_eq!(parse_srt(&format_entry_slice(&entries, 10, true)).unwrap().len(), 4);
+    }
+    
+    #[test]
+    fn mojibake_is_restored() {
+        assert_eq!(repair_mojibake_line("æ—¥æœ¬").as_deref(), Some("日本"));
+        assert_eq!(repair_mojibake_line("cafÃ©").as_deref(), Some("café"));
+        assert_eq!(repair_mojibake_line("café"), None);
+        assert_eq!(repair_mojibake_line("plain"), None);
+        
+        let output = process_srt("mojibake", &srt_indexed(&[(1, "æ—¥æœ¬"), (2, "fine")]), "", &["--fix-mojibake"]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["日本", "fine"]);
+    }
+    
+    #[test]
+    fn csv_export_quotes_commas_quotes_and_newlines() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a, b\nc"), "\"a, b\nc\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        
+        let entries = parse_srt(&srt_timed(&[(1000, 2500, "Well, yes\nno")])).unwrap();
+        assert_eq!(format_csv_export(&entries), "index,start,end,duration_ms,char_count,text\n1,\"00:00:01,000\",\"00:00:02,500\",1500,12,\"Well, yes\nno\"\n");
+    }
+    
+    #[test]
+    fn merge_by_index_pairs_entries_with_different_timestamps() {
+        let dir = temp_dir("merge-by-index");
+        let (input, output) = (dir.join("bil.srt"), dir.join("out.srt"));
+        fs::write(&input, srt_timed(&[(1000, 2000, "Hello"), (1100, 2100, "你好"), (3000, 4000, "Bye"), (3050, 4000, "再见")])).unwrap();
+        
+        run_cli(&["merge", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "--pair-by", "index"]).unwrap();
+        let entries = parse_srt(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(texts(&entries), ["Hello\n你好", "Bye\n再见"]);
+        assert_eq!(timestamps(&entries), ["00:00:01,000 --> 00:00:02,000", "00:00:03,000 --> 00:00:04,000"]);
+        
+        run_cli(&["merge", input.to_str().unwrap(), "-o", output.to_str().unwrap()]).unwrap();
+        assert_eq!(parse_srt(&fs::read_to_string(&output).unwrap()).unwrap().len(), 4);
+        
+        assert_eq!(PairBy::Index.partner(), "after it to pair by index");
+    }
+    
+    #[test]
+    fn lyrics_mode_keeps_a_blank_line_inside_a_cue() {
+        let input = "1\n00:00:01,000 --> 00:00:05,000\nFirst verse line\n\nSecond stanza line\n\n2\n00:00:06,000 --> 00:00:07,000\nChorus\n";
+        let output = process_srt("lyrics", input, "", &["--lyrics"]).unwrap();
+        assert!(output.contains("First verse line\n\nSecond stanza line\n"), "{}", output);
+        
+        let entries = parse_srt_lyrics(&output).unwrap();
+        assert_eq!(texts(&entries), ["First verse line\n\nSecond stanza line", "Chorus"]);
+        assert_eq!(format_srt_output(&entries, &OutputFormat { renumber_start: 1, ..OutputFormat::default() }), output);
+    }
+    
+    #[test]
+    fn overlap_dedup_removes_a_repeated_phrase_but_not_a_short_repeat() {
+        let mut entries = parse_srt(&srt_indexed(&[(1, "we need to go now"), (2, "to go now and then leave")])).unwrap();
+        apply_overlap_dedup(&mut entries, 3);
+        assert_eq!(texts(&entries), ["we need to go now", "and then leave"]);
+        
+        let mut entries = parse_srt(&srt_indexed(&[(1, "I said yes"), (2, "yes we can")])).unwrap();
+        apply_overlap_dedup(&mut entries, 3);
+        assert_eq!(texts(&entries), ["I said yes", "yes we can"]);
+    }
+    
+    #[test]
+    fn language_report_covers_each_file_in_a_folder() {
+        let dir = temp_dir("scan-languages");
+        fs::write(dir.join("a.srt"), srt_indexed(&[(1, "Hello there, how are you")])).unwrap();
+        fs::write(dir.join("b.srt"), srt_indexed(&[(1, "你好，你今天怎么样")])).unwrap();
+        fs::write(dir.join("c.srt"), srt_indexed(&[(1, "Good morning"), (2, "早上好")])).unwrap();
+        fs::write(dir.join("notes.txt"), "not a subtitle").unwrap();
+        
+        let report = format_language_report(&dir, false).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", report);
+        assert!(lines[0].ends_with("a.srt: en 100% (1 entries)"), "{}", report);
+        assert!(lines[1].ends_with("b.srt: zh 100% (1 entries)"), "{}", report);
+        assert!(lines[2].contains("c.srt: en ") && lines[2].contains("zh ") && lines[2].ends_with("(2 entries)"), "{}", report);
+        
+        let json = format_language_report(&dir, true).unwrap();
+        assert!(json.starts_with("[{\"file\":") && json.contains("\"entries\":2,\"languages\":[{\"code\":\"en\""), "{}", json);
+    }
+    
+    #[test]
+    fn punctuation_converts_between_full_and_half_width() {
+        assert_eq!(normalize_punctuation("你好，世界！", PunctuationStyle::Ascii), "你好,世界!");
+        assert_eq!(normalize_punctuation("Hi，there", PunctuationStyle::Ascii), "Hi, there");
+        assert_eq!(normalize_punctuation("你好, 世界!", PunctuationStyle::Cjk), "你好，世界！");
+        // Latin text keeps its ASCII punctuation
+        assert_eq!(normalize_punctuation("Hello, world 3.14", PunctuationStyle::Cjk), "Hello, world 3.14");
+    }
+    
+    #[test]
+    fn speaker_line_round_trips() {
+        let input = "1\nJOHN\n00:00:01,000 --> 00:00:02,000\nHi there\n";
+        let entries = parse_srt(input).unwrap();
+        assert_eq!(entries[0].speaker.as_deref(), Some("JOHN"));
+        assert_eq!(texts(&entries), ["Hi there"]);
+        
+        assert_eq!(format_srt_output(&entries, &OutputFormat { renumber_start: 1, keep_index_comments: true, ..OutputFormat::default() }), input);
+        assert!(!format_srt_output(&entries, &OutputFormat { renumber_start: 1, ..OutputFormat::default() }).contains("JOHN"));
+    }
+    
+    #[test]
+    fn same_speaker_cues_merge_and_different_speakers_stay_apart() {
+        let input = "1\nJOHN\n00:00:01,000 --> 00:00:02,000\nHi\n\n2\nJOHN\n00:00:02,200 --> 00:00:03,000\nthere\n\n3\nMARY\n00:00:03,100 --> 00:00:04,000\nHello\n\n4\nMARY\n00:00:09,000 --> 00:00:10,000\nLater\n";
+        let mut entries = parse_srt(input).unwrap();
+        apply_speaker_merge(&mut entries, 1000);
+        assert_eq!(texts(&entries), ["Hi there", "Hello", "Later"]);
+        assert_eq!(entries[0].timestamp, "00:00:01,000 --> 00:00:03,000");
+        
+        // A two-line cue keeps its line break when merged
+        let mut entries = parse_srt("1\nJOHN\n00:00:01,000 --> 00:00:02,000\nHi\nthere\n\n2\nJOHN\n00:00:02,200 --> 00:00:03,000\nfriend\n").unwrap();
+        apply_speaker_merge(&mut entries, 1000);
+        assert_eq!(texts(&entries), ["Hi\nthere\nfriend"]);
+    }
+    
+    #[test]
+    fn rebase_moves_the_first_cue_to_zero_and_the_rest_by_the_same_amount() {
+        let dir = temp_dir("rebase");
+        let input = dir.join("late.srt");
+        fs::write(&input, srt_timed(&[(5000, 6000, "a"), (7500, 8250, "b")])).unwrap();
+        
+        run_cli(&["rebase", input.to_str().unwrap()]).unwrap();
+        let entries = parse_srt(&fs::read_to_string(dir.join("late_rebased.srt")).unwrap()).unwrap();
+        assert_eq!(timestamps(&entries), ["00:00:00,000 --> 00:00:01,000", "00:00:02,500 --> 00:00:03,250"]);
+    }
+    
+    /// Treats each SKIP word as a regular expression
+    struct RegexMatcher;
+    
+    impl Matcher for RegexMatcher {
+        fn contains(&self, text: &str, word: &str) -> bool {
+            Regex::new(word).unwrap().is_match(text)
+        }
+    }
+    
+    #[test]
+    fn custom_matcher_decides_what_skip_rules_match() {
+        let entries = parse_srt(&srt_indexed(&[(1, "Hello"), (2, "♪ la la ♪"), (3, "call 555-1234")])).unwrap();
+        let skip_words = [r"^♪.*♪$".to_string(), r"\d{3}-\d{4}".to_string()];
+        
+        let kept: Vec<&SrtEntry> = entries.iter().filter(|e| find_skip_word(&e.text, &skip_words, &RegexMatcher).is_none()).collect();
+        assert_eq!(kept.iter().map(|e| e.text.as_str()).collect::<Vec<_>>(), ["Hello"]);
+        assert!(entries.iter().all(|e| find_skip_word(&e.text, &skip_words, &DefaultMatcher::default()).is_none()));
+    }
+    
+    #[test]
+    fn fuzzy_matching_accepts_a_near_miss_and_rejects_a_far_one() {
+        let phrases = [("thank".to_string(), "you".to_string())];
+        let combine = |text: &str, matcher: &dyn Matcher| {
+            let mut entries = parse_srt(&srt_indexed(&[(1, text), (2, "you all")])).unwrap();
+            apply_combine_rules(&mut entries, &phrases, &CombineOptions::default(), matcher, &mut Vec::new());
+            entries.len()
+        };
+        let fuzzy = FuzzyMatcher { max_distance: 1, locale: None };
+        
+        assert_eq!(combine("I want to thenk", &fuzzy), 1);
+        assert_eq!(combine("I want to thenk", &DefaultMatcher::default()), 2);
+        assert_eq!(combine("I want to thxnx", &fuzzy), 2);
+    }
     
-    let entries = parse_srt(&content)?;
+    #[test]
+    fn flatten_collapses_lines_and_the_default_keeps_them() {
+        let input = srt_indexed(&[(1, "first line\nsecond line")]);
+        let flat = process_srt("flatten", &input, "", &["--flatten"]).unwrap();
+        assert_eq!(texts(&parse_srt(&flat).unwrap()), ["first line second line"]);
+        
+        let kept = process_srt("no-flatten", &input, "", &[]).unwrap();
+        assert_eq!(texts(&parse_srt(&kept).unwrap()), ["first line\nsecond line"]);
+    }
     
-    let mut merged_entries = Vec::new();
-    let mut i = 0;
+    #[test]
+    fn roundtrip_check_catches_a_corrupt_transform() {
+        let mut entries = parse_srt(&srt_indexed(&[(1, "a"), (2, "b"), (3, "c")])).unwrap();
+        let format = OutputFormat { renumber_start: 1, ..OutputFormat::default() };
+        assert!(verify_roundtrip(&entries, &format_srt_output(&entries, &format)).is_ok());
+        
+        // A pass that empties a cue leaves a block without text, which no longer parses
+        entries[1].text.clear();
+        let error = verify_roundtrip(&entries, &format_srt_output(&entries, &format)).unwrap_err();
+        assert!(error.to_string().starts_with("Round-trip check failed: wrote 3 entries but the output parses to "), "{}", error);
+    }
     
-    while i < entries.len() {
-        if i + 1 < entries.len() && entries[i].timestamp == entries[i + 1].timestamp {
-            // Found a pair with same timestamp - merge them
-            let english_text = &entries[i].text;
-            let chinese_text = &entries[i + 1].text;
-            
-            let merged_entry = SrtEntry {
-                index: entries[i].index,
-                timestamp: entries[i].timestamp.clone(),
-                text: format!("{}\n{}", english_text, chinese_text),
-            };
-            
-            merged_entries.push(merged_entry);
-            i += 2; // Skip both entries
-        } else {
-            // Single entry, keep as is
-            merged_entries.push(entries[i].clone());
-            i += 1;
-        }
+    #[test]
+    fn durations_convert_to_frames() {
+        assert_eq!(millis_to_frames(4000, 25.0), 100);
+        assert_eq!(millis_to_frames(1040, 25.0), 26);
+        assert_eq!(millis_to_frames(1001, exact_frame_rate(23.976)), 24);
+        
+        let entries = parse_srt(&srt_timed(&[(0, 4000, "a")])).unwrap();
+        let ranges: Vec<TimeRange> = entries.iter().filter_map(|e| TimeRange::parse(&e.timestamp)).collect();
+        let stats = format_srt_stats(Path::new("x.srt"), &entries, &ranges, Some(25.0), false);
+        assert!(stats.contains("Subtitled time: 00:00:04,000 / 100 frames"), "{}", stats);
     }
     
-    let output_content = format_srt_output(&merged_entries);
+    const TAGGED_FILES: [&str; 3] = [
+        "video [Chinese (Simplified)].srt",
+        "video [English - English].srt",
+        "video [English - English-Chinese (Simplified)].srt",
+    ];
     
-    let output_path = output.clone().unwrap_or_else(|| {
-        let mut path = input.clone();
-        if let Some(stem) = path.file_stem() {
-            let new_name = format!("{}_merged.srt", stem.to_string_lossy());
-            path.set_file_name(new_name);
+    #[test]
+    fn answering_no_keeps_the_originals() {
+        begin_run(quiet(false));
+        let dir = temp_dir("confirm-deletes");
+        write_srt_files(&dir, &TAGGED_FILES);
+        let originals: Vec<PathBuf> = TAGGED_FILES.iter().map(|name| dir.join(name)).collect();
+        
+        for answer in ["no\n", "\n", ""] {
+            delete_originals(&originals, false, false, &mut io::Cursor::new(answer)).unwrap();
+            assert!(originals.iter().all(|path| path.exists()), "{:?}", answer);
         }
-        path
-    });
+        
+        delete_originals(&originals, false, false, &mut io::Cursor::new("y\n")).unwrap();
+        assert!(originals.iter().all(|path| !path.exists()));
+        
+        write_srt_files(&dir, &TAGGED_FILES);
+        delete_originals(&originals, true, false, &mut io::Cursor::new("")).unwrap();
+        assert!(originals.iter().all(|path| !path.exists()));
+    }
     
-    fs::write(&output_path, output_content)
-        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+    #[test]
+    fn pad_indices_zero_pads_and_the_default_does_not() {
+        let entries = parse_srt(&srt_indexed(&[(1, "a"), (2, "b")])).unwrap();
+        let padded = format_srt_output(&entries, &OutputFormat { renumber_start: 1, pad_indices: Some(4), ..OutputFormat::default() });
+        assert!(padded.starts_with("0001\n") && padded.contains("\n0002\n"), "{}", padded);
+        
+        let plain = format_srt_output(&entries, &OutputFormat { renumber_start: 1, ..OutputFormat::default() });
+        assert!(plain.starts_with("1\n") && plain.contains("\n2\n"), "{}", plain);
+    }
     
-    println!("Merged bilingual SRT saved to: {}", output_path.display());
-    println!("Merged {} subtitle pairs into {} entries", entries.len() / 2, merged_entries.len());
+    #[test]
+    fn bomless_utf16le_input_is_decoded() {
+        let text = srt_indexed(&[(1, "Hello"), (2, "你好")]);
+        let bytes: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(detect_encoding(&bytes), InputEncoding::Utf16le);
+        assert_eq!(decode_input(&bytes, InputEncoding::Auto).unwrap(), text);
+        
+        let dir = temp_dir("utf16");
+        fs::write(dir.join("wide.srt"), &bytes).unwrap();
+        begin_run(quiet(false));
+        assert_eq!(texts(&parse_srt(&read_input_file(&dir.join("wide.srt")).unwrap()).unwrap()), ["Hello", "你好"]);
+    }
     
-    Ok(())
-}
-
-fn batch_process_srt_files(dir: &PathBuf, config_path: &Option<PathBuf>) -> Result<()> {
-    println!("Scanning for SRT files in: {}", dir.display());
+    #[test]
+    fn interleave_combines_two_three_entry_files() {
+        let dir = temp_dir("interleave");
+        let (en, zh, output) = (dir.join("en.srt"), dir.join("zh.srt"), dir.join("bil.srt"));
+        fs::write(&en, srt_indexed(&[(1, "One"), (2, "Two"), (3, "Three")])).unwrap();
+        fs::write(&zh, srt_indexed(&[(1, "一"), (2, "二"), (3, "三")])).unwrap();
+        
+        run_cli(&["interleave", en.to_str().unwrap(), zh.to_str().unwrap(), "-o", output.to_str().unwrap()]).unwrap();
+        let entries = parse_srt(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(texts(&entries), ["One\n一", "Two\n二", "Three\n三"]);
+        assert_eq!(entries[2].timestamp, "00:00:02,000 --> 00:00:02,900");
+    }
     
-    let entries = fs::read_dir(dir)
-        .with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+    #[test]
+    fn progress_events_are_one_json_object_per_line() {
+        assert_eq!(progress_event("process", "skip", 2, 6), "{\"stage\":\"process\",\"step\":\"skip\",\"done\":2,\"total\":6}");
+        assert_eq!(progress_event("batch", "a\"b", 0, 1), "{\"stage\":\"batch\",\"step\":\"a\\\"b\",\"done\":0,\"total\":1}");
+        
+        // Events go to stderr, so the written output is the same with or without them
+        let input = srt_indexed(&[(1, "a"), (2, "[music]")]);
+        assert_eq!(
+            process_srt("progress-json", &input, "SKIP: \"music\"\n", &["--progress-json"]).unwrap(),
+            process_srt("no-progress-json", &input, "SKIP: \"music\"\n", &[]).unwrap(),
+        );
+    }
     
-    let mut srt_files = Vec::new();
+    #[test]
+    fn combine_lookahead_reaches_past_an_intervening_cue() {
+        let input = srt_indexed(&[(1, "I want to thank"), (2, "um"), (3, "you all")]);
+        let phrases = [("thank".to_string(), "you".to_string())];
+        let combine = |lookahead| {
+            let mut entries = parse_srt(&input).unwrap();
+            apply_combine_rules(&mut entries, &phrases, &CombineOptions { lookahead, ..CombineOptions::default() }, &DefaultMatcher::default(), &mut Vec::new());
+            entries
+        };
+        
+        assert_eq!(texts(&combine(0)), ["I want to thank", "um", "you all"]);
+        assert_eq!(texts(&combine(1)), ["I want to thank um you all"]);
+    }
     
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
+    #[test]
+    fn unused_rules_are_reported_and_used_ones_are_not() {
+        let config = Config::from_content("SKIP: \"music\", \"laughter\"\nCOMBINE: \"thank you\", \"drop out\"\nCOMBINE_EQUAL: \"no\"\nEND: \"and\", \"the\"\n").unwrap();
+        let mut entries = parse_srt(&srt_indexed(&[(1, "[music]"), (2, "I want to thank"), (3, "you all and"), (4, "more")])).unwrap();
         
-        if path.extension().and_then(|s| s.to_str()) == Some("srt") {
-            if let Some(filename) = path.file_name().and_then(|s| s.to_str()) {
-                srt_files.push((path.clone(), filename.to_string()));
-            }
+        let skip_hits: BTreeSet<String> = entries.iter()
+            .filter_map(|e| find_skip_word(&e.text, &config.skip_words, &DefaultMatcher::default()).cloned())
+            .collect();
+        entries.retain(|e| find_skip_word(&e.text, &config.skip_words, &DefaultMatcher::default()).is_none());
+        let combine_equal_hits = apply_combine_equal(&mut entries, &config.combine_equal, &mut Vec::new());
+        let combine_hits = apply_combine_rules(&mut entries, &config.combine_phrases, &CombineOptions::default(), &DefaultMatcher::default(), &mut Vec::new());
+        let end_hits = apply_end_rules(&mut entries, &config.end_words, false, false, &DefaultMatcher::default(), &mut Vec::new());
+        
+        assert_eq!(unused_rules(&config, &skip_hits, &combine_hits, &combine_equal_hits, &end_hits), [
+            "SKIP \"laughter\"",
+            "COMBINE \"drop out\"",
+            "COMBINE_EQUAL \"no\"",
+            "END \"the\"",
+        ]);
+    }
+    
+    #[test]
+    fn process_explains_a_directory_or_missing_input() {
+        let dir = temp_dir("process-input-kind");
+        
+        let error = run_cli(&["process", dir.to_str().unwrap()]).unwrap_err().to_string();
+        assert!(error.contains("is a directory; did you mean `srt-handle batch -d"), "{}", error);
+        
+        let missing = dir.join("missing.srt");
+        let error = run_cli(&["process", missing.to_str().unwrap()]).unwrap_err().to_string();
+        assert_eq!(error, format!("Input file not found: {}", missing.display()));
+    }
+    
+    #[test]
+    fn batch_lang_suffix_names_come_from_the_video_name() {
+        let dir = temp_dir("batch-lang-suffix");
+        write_srt_files(&dir, &TAGGED_FILES);
+        
+        let plan = plan_batch_folder(&dir, &batch_options(&["--naming", "lang-suffix"]), 1).unwrap().unwrap();
+        let targets: Vec<String> = plan.copies.iter().map(|(_, target, _)| file_name_of(target)).collect();
+        assert_eq!(targets, ["video.zh.srt", "video.en.srt", "video.en-zh.srt"]);
+        assert_eq!(plan.process, Some((dir.join("video.en.srt"), dir.join("video.en_ok.srt"))));
+        assert_eq!(plan.originals.len(), 3);
+        
+        let plan = plan_batch_folder(&dir, &batch_options(&[]), 1).unwrap().unwrap();
+        let targets: Vec<String> = plan.copies.iter().map(|(_, target, _)| file_name_of(target)).collect();
+        assert_eq!(targets, ["zh_srt.srt", "en_srt.srt", "bil_srt.srt"]);
+    }
+    
+    #[test]
+    fn batch_rerun_never_deletes_its_own_outputs() {
+        for naming in ["fixed", "lang-suffix"] {
+            let dir = temp_dir(&format!("batch-rerun-{}", naming));
+            write_srt_files(&dir, &TAGGED_FILES);
+            write_srt_files(&dir, &["zh_srt.srt", "en_srt.srt", "bil_srt.srt", "en_srt_ok.srt"]);
+            write_srt_files(&dir, &["video.zh.srt", "video.en.srt", "video.en-zh.srt", "video.en_ok.srt", "notes.srt"]);
+            
+            let plan = plan_batch_folder(&dir, &batch_options(&["--naming", naming]), 1).unwrap().unwrap();
+            let sources: Vec<String> = plan.copies.iter().map(|(source, _, _)| file_name_of(source)).collect();
+            assert_eq!(sources, TAGGED_FILES);
+            let mut originals: Vec<String> = plan.originals.iter().map(|path| file_name_of(path)).collect();
+            originals.sort_by_key(|name| TAGGED_FILES.iter().position(|tagged| tagged == name));
+            assert_eq!(originals, TAGGED_FILES, "--naming {}", naming);
         }
     }
     
-    if srt_files.is_empty() {
-        println!("No SRT files found in directory.");
-        return Ok(());
+    #[test]
+    fn batch_keeps_a_tagged_file_its_template_writes_to() {
+        let dir = temp_dir("batch-template");
+        write_srt_files(&dir, &TAGGED_FILES);
+        
+        let plan = plan_batch_folder(&dir, &batch_options(&["--output-template", "video [English - English].srt"]), 1).unwrap().unwrap();
+        assert!(!plan.originals.contains(&dir.join("video [English - English].srt")));
+        assert_eq!(plan.originals.len(), 2);
     }
     
-    println!("Found {} SRT files", srt_files.len());
+    const ANNOTATED: &str = "// check the name\n\n1\n00:00:01,000 --> 00:00:02,000\nHello\n\n// fix timing\n2\n00:00:03,000 --> 00:00:04,000\nsee http:\n// not a note\n";
     
-    let mut zh_files = Vec::new();
-    let mut en_files = Vec::new();
-    let mut bil_files = Vec::new();
+    #[test]
+    fn annotations_are_kept_apart_and_become_vtt_notes() {
+        let entries = parse_srt(ANNOTATED).unwrap();
+        assert_eq!(entries[0].notes, ["check the name"]);
+        assert_eq!(entries[1].notes, ["fix timing"]);
+        assert_eq!(entries[1].text, "see http:\n// not a note");
+        
+        let vtt = format_vtt_export(&entries);
+        assert!(vtt.contains("NOTE\ncheck the name\n\n1\n00:00:01.000 --> 00:00:02.000\nHello"), "{}", vtt);
+        assert!(vtt.contains("NOTE\nfix timing\n"), "{}", vtt);
+    }
     
-    for (path, filename) in &srt_files {
-        if filename.contains("[Chinese (Simplified)]") {
-            zh_files.push(path.clone());
-        } else if filename.contains("[English - English-Chinese (Simplified)]") {
-            bil_files.push(path.clone());
-        } else if filename.contains("[English - English]") {
-            en_files.push(path.clone());
-        }
+    #[test]
+    fn annotations_reach_srt_output_only_when_asked() {
+        let entries = parse_srt(ANNOTATED).unwrap();
+        
+        let plain = format_srt_output(&entries, &OutputFormat { renumber_start: 1, ..OutputFormat::default() });
+        assert!(!plain.contains("check the name") && !plain.contains("fix timing"), "{}", plain);
+        assert!(plain.starts_with("1\n00:00:01,000"), "{}", plain);
+        
+        let kept = format_srt_output(&entries, &OutputFormat { keep_annotations: true, ..OutputFormat::default() });
+        assert!(kept.starts_with("// check the name\n\n"), "{}", kept);
+        let reparsed = parse_srt(&kept).unwrap();
+        assert_eq!(reparsed.iter().map(|e| e.notes.clone()).collect::<Vec<_>>(), [vec!["check the name"], vec!["fix timing"]]);
     }
     
-    if en_files.len() > 1 {
-        println!("Warning: Found {} files with '[English - English]', only processing the first one", en_files.len());
+    #[test]
+    fn speed_report_flags_a_problem_entry() {
+        let long_line = "This line is much wider than forty-two columns of text";
+        let entries = parse_srt(&srt_timed(&[(0, 2000, "Fine"), (3000, 3300, "Far too much text for this cue"), (4000, 9000, long_line)])).unwrap();
+        let report = format_speed_report(&entries, 700, &OutputFormat { renumber_start: 1, ..OutputFormat::default() });
+        let lines: Vec<&str> = report.lines().collect();
+        
+        assert_eq!(lines[0], "Speed report: 2 entries need attention");
+        assert!(lines[2].trim_start().starts_with("2  00:00:03,000") && lines[2].ends_with("fast,short"), "{}", report);
+        assert!(lines[3].trim_start().starts_with("3  ") && lines[3].ends_with("long-line"), "{}", report);
+        assert_eq!(lines.len(), 4, "{}", report);
     }
     
-    let mut processed_files = Vec::new();
+    #[test]
+    fn no_renumber_keeps_original_indices_through_process() {
+        let input = srt_indexed(&[(10, "a"), (20, "[music]"), (30, "c")]);
+        let output = process_srt("no-renumber", &input, "SKIP: \"music\"\n", &["--no-renumber"]).unwrap();
+        assert_eq!(parse_srt(&output).unwrap().iter().map(|e| e.index).collect::<Vec<_>>(), [10, 30]);
+        
+        let output = process_srt("renumber", &input, "SKIP: \"music\"\n", &[]).unwrap();
+        assert_eq!(parse_srt(&output).unwrap().iter().map(|e| e.index).collect::<Vec<_>>(), [1, 2]);
+        
+        // A cue split off by an END rule is written with the index of the cue it came from
+        let output = process_srt("no-renumber-split", &srt_indexed(&[(10, "wait and see"), (20, "c")]), "END: \"and\"\n", &["--no-renumber", "--end-as-split"]).unwrap();
+        assert_eq!(parse_srt(&output).unwrap().iter().map(|e| e.index).collect::<Vec<_>>(), [10, 10, 20]);
+    }
+    
+    #[test]
+    fn long_cues_split_by_words_or_duplicated() {
+        let input = srt_timed(&[(0, 18_000, "one two three four five six")]);
+        let parts = [
+            "00:00:00,000 --> 00:00:06,000",
+            "00:00:06,000 --> 00:00:12,000",
+            "00:00:12,000 --> 00:00:18,000",
+        ];
+        
+        let mut entries = parse_srt(&input).unwrap();
+        apply_max_cue_duration(&mut entries, 6000, LongCueSplit::Words);
+        assert_eq!(texts(&entries), ["one two", "three four", "five six"]);
+        assert_eq!(timestamps(&entries), parts);
+        
+        // A two-line cue keeps its line break inside the part that spans it
+        let mut entries = parse_srt(&srt_timed(&[(0, 12_000, "one two three\nfour five six")])).unwrap();
+        apply_max_cue_duration(&mut entries, 6000, LongCueSplit::Words);
+        assert_eq!(texts(&entries), ["one two three", "four five six"]);
+        let mut entries = parse_srt(&srt_timed(&[(0, 18_000, "one two three\nfour five six")])).unwrap();
+        apply_max_cue_duration(&mut entries, 6000, LongCueSplit::Words);
+        assert_eq!(texts(&entries), ["one two", "three\nfour", "five six"]);
+        
+        let mut entries = parse_srt(&input).unwrap();
+        apply_max_cue_duration(&mut entries, 6000, LongCueSplit::Duplicate);
+        assert_eq!(texts(&entries), ["one two three four five six"; 3]);
+        assert_eq!(timestamps(&entries), parts);
+    }
     
-    if let Some(zh_file) = zh_files.first() {
-        let target_path = dir.join("zh_srt.srt");
-        fs::copy(zh_file, &target_path)
-            .with_context(|| format!("Failed to copy {} to zh_srt.srt", zh_file.display()))?;
-        println!("Renamed Chinese file to: zh_srt.srt");
-        processed_files.push("zh_srt.srt");
+    #[test]
+    fn existing_output_needs_force() {
+        let (dir, args) = process_args("force", &srt_indexed(&[(1, "new")]), "");
+        let cli: Vec<String> = std::iter::once("srt-handle".to_string()).chain(args).collect();
+        fs::write(dir.join("out.srt"), "old").unwrap();
+        
+        begin_run(quiet(false));
+        let error = run(Args::try_parse_from(&cli).unwrap()).unwrap_err().to_string();
+        assert!(error.contains("already exists; use --force to overwrite it"), "{}", error);
+        assert_eq!(fs::read_to_string(dir.join("out.srt")).unwrap(), "old");
+        
+        let mut forced = cli.clone();
+        forced.push("--force".to_string());
+        begin_run(RunSettings::from_args(&Args::try_parse_from(&forced).unwrap()));
+        run(Args::try_parse_from(&forced).unwrap()).unwrap();
+        assert_eq!(texts(&parse_srt(&fs::read_to_string(dir.join("out.srt")).unwrap()).unwrap()), ["new"]);
     }
     
-    if let Some(en_file) = en_files.first() {
-        let target_path = dir.join("en_srt.srt");
-        fs::copy(en_file, &target_path)
-            .with_context(|| format!("Failed to copy {} to en_srt.srt", en_file.display()))?;
-        println!("Renamed English file to: en_srt.srt");
-        processed_files.push("en_srt.srt");
+    #[test]
+    fn newest_duplicate_policy_picks_the_latest_file() {
+        begin_run(quiet(false));
+        let dir = temp_dir("duplicate-newest");
+        let (older, newer) = (dir.join("a [English - English].srt"), dir.join("b [English - English].srt"));
+        write_srt_files(&dir, &["a [English - English].srt", "b [English - English].srt"]);
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        fs::File::options().write(true).open(&older).unwrap().set_modified(an_hour_ago).unwrap();
+        
+        let files = [older.clone(), newer.clone()];
+        assert_eq!(pick_batch_source(&files, "[English - English]", DuplicatePolicy::Newest).unwrap(), Some(newer.clone()));
+        assert_eq!(pick_batch_source(&[newer, older.clone()], "[English - English]", DuplicatePolicy::First).unwrap(), Some(files[1].clone()));
+        assert!(pick_batch_source(&files, "[English - English]", DuplicatePolicy::Error).is_err());
     }
     
-    if let Some(bil_file) = bil_files.first() {
-        let target_path = dir.join("bil_srt.srt");
-        fs::copy(bil_file, &target_path)
-            .with_context(|| format!("Failed to copy {} to bil_srt.srt", bil_file.display()))?;
-        println!("Renamed bilingual file to: bil_srt.srt");
-        processed_files.push("bil_srt.srt");
+    #[test]
+    fn speaker_labels_are_stripped_but_times_of_day_are_not() {
+        let default = Regex::new(SPEAKER_LABEL_PATTERN).unwrap();
+        assert_eq!(strip_speaker_label("JOHN: Hello there", &default), "Hello there");
+        assert_eq!(strip_speaker_label("MR. SMITH: Hi\nsecond line", &default), "Hi\nsecond line");
+        assert_eq!(strip_speaker_label("Meet me at 10:30", &default), "Meet me at 10:30");
+        
+        let loose = Regex::new(r"^[A-Z0-9 ]+:\s*").unwrap();
+        assert_eq!(strip_speaker_label("AT 10:30 we leave", &loose), "AT 10:30 we leave");
+        assert_eq!(strip_speaker_label("GUARD 2: Halt", &loose), "Halt");
     }
     
-    if processed_files.contains(&"en_srt.srt") {
-        println!("Processing en_srt.srt for improved readability...");
+    #[test]
+    fn three_same_timestamp_entries_merge_into_one_cue() {
+        let dir = temp_dir("merge-lines");
+        let (input, output) = (dir.join("tri.srt"), dir.join("out.srt"));
+        fs::write(&input, srt_timed(&[(1000, 2000, "Hello"), (1000, 2000, "你好"), (1000, 2000, "Bonjour"), (3000, 4000, "Bye"), (3000, 4000, "再见"), (3000, 4000, "Au revoir")])).unwrap();
         
-        let en_srt_path = dir.join("en_srt.srt");
+        run_cli(&["merge", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "--lines", "3"]).unwrap();
+        let entries = parse_srt(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(texts(&entries), ["Hello\n你好\nBonjour", "Bye\n再见\nAu revoir"]);
+    }
+    
+    #[test]
+    fn resegment_gives_each_sentence_a_share_of_the_time() {
+        let dir = temp_dir("resegment");
+        let (input, output) = (dir.join("in.srt"), dir.join("out.srt"));
+        fs::write(&input, srt_timed(&[(1000, 3000, "Hello there. How are"), (3000, 3900, "you doing? Fine.")])).unwrap();
         
-        let mut cmd = Command::new(env::current_exe().unwrap_or_else(|_| PathBuf::from("srt-handle")));
-        cmd.arg("process").arg(&en_srt_path).current_dir(dir);
+        run_cli(&["resegment", input.to_str().unwrap(), "-o", output.to_str().unwrap()]).unwrap();
+        let entries = parse_srt(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(texts(&entries), ["Hello there.", "How are you doing?", "Fine."]);
+        let ranges: Vec<TimeRange> = entries.iter().map(|e| TimeRange::parse(&e.timestamp).unwrap()).collect();
+        assert_eq!(ranges[0].start.to_string(), "00:00:01,000");
+        assert_eq!(ranges[2].end.to_string(), "00:00:03,900");
+        assert!(ranges.windows(2).all(|pair| pair[0].end == pair[1].start));
+        assert!(ranges[1].duration_ms() > ranges[0].duration_ms() && ranges[0].duration_ms() > ranges[2].duration_ms());
+    }
+    
+    #[test]
+    fn smart_punct_cleans_a_mid_sentence_join_only() {
+        assert_eq!(join_combined_text("I went there,", "And then left", true), "I went there and then left");
+        assert_eq!(join_combined_text("It ended.", "Then we left", true), "It ended. Then we left");
+        assert_eq!(join_combined_text("So,", "I think", true), "So I think");
+        assert_eq!(join_combined_text("I went there,", "And then left", false), "I went there, And then left");
+    }
+    
+    #[test]
+    fn frame_rate_is_read_from_the_file_name() {
+        assert_eq!(fps_from_file_name(Path::new("Movie.2019.1080p.23.976fps.srt")), Some(23.976));
+        assert_eq!(fps_from_file_name(Path::new("show_s01e02_25p.srt")), Some(25.0));
+        assert_eq!(fps_from_file_name(Path::new("clip 29.97.srt")), Some(29.97));
+        assert_eq!(fps_from_file_name(Path::new("episode 12.srt")), None);
+        assert_eq!(fps_from_file_name(Path::new("Movie.HDR10p.srt")), None);
+        assert_eq!(fps_from_file_name(Path::new("Movie.x264-10bit.srt")), None);
+    }
+    
+    #[test]
+    fn bilingual_file_is_detected_and_merged() {
+        let input = srt_timed(&[(1000, 2000, "I want to thank"), (1000, 2000, "我想感谢"), (2000, 3000, "you all"), (2000, 3000, "你们")]);
+        let output = process_srt("detect-bilingual", &input, "COMBINE: \"thank you\"\n", &["--detect-bilingual"]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["I want to thank\n我想感谢", "you all\n你们"]);
         
-        // Only add config argument if one was specified
-        if let Some(config_path) = config_path {
-            cmd.arg("-c").arg(config_path);
-        }
+        // Without the flag the file is processed as it is
+        let output = process_srt("detect-bilingual-off", &input, "", &[]).unwrap();
+        assert_eq!(parse_srt(&output).unwrap().len(), 4);
         
-        let output = cmd.output()
-            .with_context(|| "Failed to execute srt-handle process command")?;
+        assert!(looks_bilingual(&parse_srt(&input).unwrap(), 0));
+        assert!(!looks_bilingual(&parse_srt(&srt_indexed(&[(1, "a"), (2, "b"), (3, "c")])).unwrap(), 0));
+    }
+    
+    #[test]
+    fn audit_log_lists_what_each_pass_did() {
+        let (dir, mut cli) = process_args("audit", &srt_indexed(&[(1, "Hi"), (2, "[music]"), (3, "I want to thank"), (4, "you all")]), "SKIP: \"music\"\nCOMBINE: \"thank you\"\n");
+        cli.extend(["--audit".to_string(), dir.join("audit.tsv").to_string_lossy().into_owned()]);
+        run_cli(&cli.iter().map(String::as_str).collect::<Vec<_>>()).unwrap();
         
-        if output.status.success() {
-            println!("Successfully processed en_srt.srt -> en_srt_ok.srt");
-            
-            println!("Cleaning up original files...");
-            for (original_file, _) in &srt_files {
-                if let Err(e) = fs::remove_file(original_file) {
-                    eprintln!("Warning: Failed to delete {}: {}", original_file.display(), e);
-                } else {
-                    println!("Deleted: {}", original_file.display());
+        assert_eq!(fs::read_to_string(dir.join("audit.tsv")).unwrap(), "2\tskip\tskipped\n3\tcombine\tcombined with 4 (\"thank\" / \"you\")\n4\tcombine\tabsorbed into 3\n");
+        
+        // An existing audit log stops the run before the output is written
+        fs::remove_file(dir.join("out.srt")).unwrap();
+        let cli: Vec<String> = std::iter::once("srt-handle".to_string()).chain(cli).collect();
+        begin_run(quiet(false));
+        let error = run(Args::try_parse_from(&cli).unwrap()).unwrap_err().to_string();
+        assert!(error.contains("audit.tsv already exists"), "{}", error);
+        assert!(!dir.join("out.srt").exists());
+    }
+    
+    #[test]
+    fn approx_eq_includes_the_tolerance_boundary() {
+        let range = TimeRange::parse("00:00:01,000 --> 00:00:02,000").unwrap();
+        let moved = |start: u64, end: u64| TimeRange { start: Timestamp::from_millis(start), end: Timestamp::from_millis(end) };
+        
+        assert!(range.approx_eq(&range, 0));
+        assert!(!range.approx_eq(&moved(1001, 2000), 0));
+        assert!(range.approx_eq(&moved(1100, 1900), 100));
+        assert!(!range.approx_eq(&moved(1101, 2000), 100));
+        assert!(!range.approx_eq(&moved(1000, 2101), 100));
+        assert!(range.approx_eq(&moved(900, 2100), 100));
+        assert!(timestamps_match("00:00:01,000 --> 00:00:02,000", "00:00:01,050 --> 00:00:02,000", 50));
+        
+        // Bilingual detection uses the same tolerance
+        let nearly = srt_timed(&[(1000, 2000, "Hello"), (1040, 2000, "你好"), (2000, 3000, "Bye"), (2000, 3030, "再见")]);
+        assert!(!looks_bilingual(&parse_srt(&nearly).unwrap(), 0));
+        assert!(looks_bilingual(&parse_srt(&nearly).unwrap(), 50));
+    }
+    
+    #[test]
+    fn validate_expectations_pass_and_fail() {
+        let entries = parse_srt(&srt_timed(&[(0, 1000, "ten chars!"), (2000, 10_000, "a long cue")])).unwrap();
+        
+        assert!(validation_problems(&entries, Some(2), Some(10.0), Some(8000)).is_empty());
+        assert_eq!(validation_problems(&entries, Some(3), None, None), ["--expect-min-entries 3 failed: found 2 entries"]);
+        assert_eq!(validation_problems(&entries, None, Some(9.5), None), ["--expect-max-cps 9.5 failed: 1 entries over, fastest is entry 1 at 10.0 chars/s"]);
+        assert_eq!(validation_problems(&entries, None, None, Some(7999)), ["--expect-max-cue 7999ms failed: 1 entries over, longest is entry 2 at 8000ms"]);
+        assert_eq!(chars_per_second("你好\nab", 1000), 6.0);
+        
+        let dir = temp_dir("validate");
+        let input = dir.join("in.srt");
+        fs::write(&input, srt_timed(&[(0, 1000, "ten chars!"), (2000, 10_000, "a long cue")])).unwrap();
+        assert!(run_cli(&["validate", input.to_str().unwrap(), "--expect-min-entries", "2"]).is_ok());
+        let error = run_cli(&["validate", input.to_str().unwrap(), "--expect-max-cue", "7s"]).unwrap_err();
+        assert_eq!(error.to_string(), format!("1 problem(s) found in {}", input.display()));
+    }
+    
+    #[test]
+    fn streaming_matches_in_memory_processing() {
+        let dir = temp_dir("stream");
+        let input = "Title: demo\nKind: captions\n\n1\n00:00:01,000 --> 00:00:02,000\nList:\n\n2. second item\n\n// note\n\n\n2\n00:00:03,000 --> 00:00:04,000\n[music]\n\n3\n00:00:05,000 --> 00:00:06,000\nbye\n// trailing\n\n// after\n";
+        fs::write(dir.join("config.txt"), "SKIP: \"[music]\"\n").unwrap();
+        
+        for (name, content) in [("lf.srt", input.to_string()), ("crlf.srt", input.replace('\n', "\r\n"))] {
+            let path = dir.join(name);
+            fs::write(&path, content).unwrap();
+            let config = dir.join("config.txt");
+            let (memory, streamed) = (dir.join("memory.srt"), dir.join("streamed.srt"));
+            for (output, stream) in [(&memory, false), (&streamed, true)] {
+                let mut args = vec!["process", path.to_str().unwrap(), "-o", output.to_str().unwrap(), "-c", config.to_str().unwrap(), "--keep-annotations"];
+                if stream {
+                    args.push("--stream");
                 }
+                run_cli(&args).unwrap();
             }
             
-        } else {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            eprintln!("Failed to process en_srt.srt: {}", stderr);
+            let memory = fs::read_to_string(&memory).unwrap();
+            assert_eq!(memory, fs::read_to_string(&streamed).unwrap(), "{}", name);
+            assert!(memory.contains("List:\n\n2. second item") && !memory.contains("Title") && !memory.contains("[music]"), "{}", memory);
         }
     }
     
-    println!("Batch processing completed.");
-    println!("Processed files: {}", processed_files.join(", "));
+    #[test]
+    fn negative_shift_counts_the_clamped_entries() {
+        let mut entries = parse_srt(&srt_timed(&[(1000, 6000, "a"), (3000, 4000, "b"), (8000, 9000, "c")])).unwrap();
+        assert_eq!(shift_timestamps(&mut entries, -5000).unwrap(), 2);
+        assert_eq!(timestamps(&entries), ["00:00:00,000 --> 00:00:01,000", "00:00:00,000 --> 00:00:00,000", "00:00:03,000 --> 00:00:04,000"]);
+    }
     
-    Ok(())
-}
-
-fn process_single_file(input: &PathBuf, output: &Option<PathBuf>, config_path: &Option<PathBuf>) -> Result<()> {
-    let config = if let Some(path) = config_path {
-        Config::from_file(path)?
-    } else {
-        Config::from_embedded()?
-    };
+    #[test]
+    fn grouped_merge_pairs_the_sections_by_position() {
+        let dir = temp_dir("merge-grouped");
+        let (input, output) = (dir.join("grouped.srt"), dir.join("out.srt"));
+        let content = format!("{}---\n\n{}", srt_timed(&[(1000, 2000, "Hello"), (3000, 4000, "Bye")]), srt_timed(&[(1100, 2000, "你好"), (3000, 4100, "再见")]));
+        fs::write(&input, content).unwrap();
+        
+        run_cli(&["merge", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "--grouped"]).unwrap();
+        let entries = parse_srt(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(texts(&entries), ["Hello\n你好", "Bye\n再见"]);
+        assert_eq!(entries[0].timestamp, "00:00:01,000 --> 00:00:02,000");
+    }
     
-    let content = fs::read_to_string(input)
-        .with_context(|| format!("Failed to read input file: {}", input.display()))?;
+    #[test]
+    fn strict_srt_refuses_to_write_an_overlong_cue() {
+        let input = srt_indexed(&[(1, "short"), (2, "this cue has far too many characters")]);
+        let (dir, mut cli) = process_args("strict-srt", &input, "");
+        cli.extend(["--strict-srt", "--spec-max-chars", "20"].map(String::from));
+        
+        let error = run_cli(&cli.iter().map(String::as_str).collect::<Vec<_>>()).unwrap_err();
+        assert_eq!(error.to_string(), "1 cue(s) break the --strict-srt limits; output not written");
+        assert!(!dir.join("out.srt").exists());
+        
+        assert!(process_srt("strict-srt-ok", &input, "", &["--strict-srt"]).is_ok());
+    }
     
-    let mut entries = parse_srt(&content)?;
+    #[test]
+    fn comma_and_point_decimals_parse_alike() {
+        assert_eq!(parse_duration_ms("1,5s"), Ok(1500));
+        assert_eq!(parse_duration_ms("1.5s"), Ok(1500));
+        assert_eq!(parse_duration_ms("0,25s"), parse_duration_ms("250ms"));
+        assert_eq!(parse_signed_duration_ms("-1,5s"), Ok(-1500));
+        assert_eq!(parse_decimal("1,001"), parse_decimal("1.001"));
+        assert!(parse_duration_ms("1,5,0s").is_err());
+    }
     
-    entries.retain(|entry| !should_skip_entry(&entry.text, &config.skip_words));
+    #[test]
+    fn segment_writes_one_file_per_silence_separated_part() {
+        let dir = temp_dir("segment");
+        let input = dir.join("talk.srt");
+        fs::write(&input, "1\n00:00:01,000 --> 00:00:02,000\nfirst\n\n2\n00:00:02,500 --> 00:00:03,000\nstill first\n\n3\n00:00:43,000 --> 00:00:44,000\nsecond\n").unwrap();
+        
+        run_cli(&["segment", input.to_str().unwrap(), "--min-gap", "30s"]).unwrap();
+        
+        let first = parse_srt(&fs::read_to_string(dir.join("talk_1_00-00-01-000.srt")).unwrap()).unwrap();
+        assert_eq!(texts(&first), ["first", "still first"]);
+        let second = parse_srt(&fs::read_to_string(dir.join("talk_2_00-00-43-000.srt")).unwrap()).unwrap();
+        assert_eq!(texts(&second), ["second"]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+    }
     
-    apply_combine_rules(&mut entries, &config.combine_phrases);
+    #[test]
+    fn segment_names_stay_unique_within_a_second() {
+        let start = Timestamp::parse("00:01:02,500").unwrap();
+        assert_eq!(segment_file_name("ep", 3, 12, start), "ep_03_00-01-02-500.srt");
+        assert_ne!(segment_file_name("ep", 1, 2, start), segment_file_name("ep", 2, 2, start));
+    }
     
-    apply_end_rules(&mut entries, &config.end_words);
+    #[test]
+    fn only_skip_leaves_combine_matches_unmerged() {
+        let input = srt_indexed(&[(1, "[music]"), (2, "I want to thank"), (3, "you all")]);
+        let config = "SKIP: \"music\"\nCOMBINE: \"thank you\"\n";
+        
+        let output = process_srt("only-skip", &input, config, &["--only", "skip"]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["I want to thank", "you all"]);
+        let output = process_srt("skip-rule-skip", &input, config, &["--skip-rule", "skip"]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["[music]", "I want to thank you all"]);
+    }
     
-    let output_content = format_srt_output(&entries);
+    #[test]
+    fn round_to_snaps_timestamps_to_the_nearest_step() {
+        assert_eq!(Timestamp::parse("00:00:01,049").unwrap().rounded(100).to_string(), "00:00:01,000");
+        assert_eq!(Timestamp::parse("00:00:01,050").unwrap().rounded(100).to_string(), "00:00:01,100");
+        assert_eq!(Timestamp::parse("00:00:59,960").unwrap().rounded(100).to_string(), "00:01:00,000");
+        
+        let output = process_srt("round-to", &srt_timed(&[(1049, 2951, "a")]), "", &["--round-to", "100ms"]).unwrap();
+        assert_eq!(parse_srt(&output).unwrap()[0].timestamp, "00:00:01,000 --> 00:00:03,000");
+    }
     
-    let output_path = output.clone().unwrap_or_else(|| {
-        let mut path = input.clone();
-        if let Some(stem) = path.file_stem() {
-            let new_name = format!("{}_ok.srt", stem.to_string_lossy());
-            path.set_file_name(new_name);
-        }
-        path
-    });
+    #[test]
+    fn doctor_reports_the_embedded_rule_counts() {
+        let config = Config::from_embedded().unwrap();
+        let counts = format_rule_counts(&config);
+        assert!(counts.contains(&format!("  SKIP: {}\n", config.skip_words.len())), "{}", counts);
+        assert!(counts.contains(&format!("  COMBINE: {}\n", config.combine_phrases.len())), "{}", counts);
+        assert!(counts.contains(&format!("  END: {}\n", config.end_words.len())), "{}", counts);
+        assert!(!config.skip_words.is_empty());
+        
+        begin_run(quiet(false));
+        run_doctor().unwrap();
+    }
     
-    fs::write(&output_path, output_content)
-        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+    #[test]
+    fn dedup_on_merge_keeps_one_of_two_identical_lines() {
+        let entries = parse_srt(&srt_timed(&[(1000, 2000, "Hello"), (1000, 2000, "hello "), (3000, 4000, "Bye"), (3000, 4000, "再见")])).unwrap();
+        let options = |dedup_on_merge| MergeOptions { separator: "\n".to_string(), pair_by: PairBy::Timestamp, timestamp_tolerance_ms: 0, dedup_on_merge };
+        
+        assert_eq!(texts(&merge_entry_runs(&entries, 2, &options(true))), ["Hello", "Bye\n再见"]);
+        assert_eq!(texts(&merge_entry_runs(&entries, 2, &options(false))), ["Hello\nhello ", "Bye\n再见"]);
+    }
     
-    println!("Processed SRT file saved to: {}", output_path.display());
+    #[test]
+    fn diff_shows_a_skipped_cue_as_a_removal() {
+        let old = parse_srt(&srt_indexed(&[(1, "Hi"), (2, "[music]"), (3, "Bye")])).unwrap();
+        let new: Vec<SrtEntry> = old.iter().filter(|e| e.text != "[music]").cloned().collect();
+        let diff = unified_cue_diff(&old, &new, Path::new("in.srt"), Path::new("out.srt"), &OutputFormat::default());
+        
+        assert!(diff.starts_with("--- in.srt\n+++ out.srt\n@@ "), "{}", diff);
+        assert!(diff.contains("\n-2\n-00:00:01,000 --> 00:00:01,900\n-[music]\n"), "{}", diff);
+        assert!(!diff.lines().any(|line| line.starts_with('+') && !line.starts_with("+++")), "{}", diff);
+    }
     
-    Ok(())
-}
-
-fn main() -> Result<()> {
-    let args = Args::parse();
+    #[test]
+    fn batch_reads_a_zip_bundle() {
+        let dir = temp_dir("zip");
+        let archive = dir.join("bundle.zip");
+        let cue = srt_indexed(&[(1, "你好")]);
+        fs::write(&archive, stored_zip(&[("season 1/show [Chinese (Simplified)].srt", &cue), ("readme.txt", "not a subtitle")])).unwrap();
+        
+        let extracted = extract_zip_archive(&archive).unwrap();
+        assert!(extracted.is_absolute());
+        assert_eq!(extracted, std::path::absolute(dir.join("bundle")).unwrap());
+        
+        run_cli(&["batch", "-d", archive.to_str().unwrap(), "--yes"]).unwrap();
+        assert_eq!(fs::read_to_string(extracted.join("zh_srt.srt")).unwrap(), cue);
+        assert!(!extracted.join("readme.txt").exists());
+    }
     
-    match args.command {
-        Commands::Process { input, output, config } => {
-            process_single_file(&input, &output, &config)?;
-        }
-        Commands::Batch { dir, config } => {
-            batch_process_srt_files(&dir, &config)?;
-        }
-        Commands::Merge { input, output } => {
-            merge_bilingual_srt(&input, &output)?;
+    #[test]
+    fn zip_files_sharing_a_name_are_rejected() {
+        let dir = temp_dir("zip-collision");
+        let archive = dir.join("bundle.zip");
+        let cue = srt_indexed(&[(1, "hello")]);
+        fs::write(&archive, stored_zip(&[("a/show.en.srt", &cue), ("b/show.en.srt", &cue)])).unwrap();
+        
+        let err = extract_zip_archive(&archive).unwrap_err().to_string();
+        assert!(err.contains("a/show.en.srt") && err.contains("b/show.en.srt"), "{}", err);
+        assert!(!dir.join("bundle").exists());
+    }
+    
+    #[test]
+    fn inflate_decodes_each_block_type() {
+        // Raw DEFLATE streams from zlib: a fixed-Huffman block, a dynamic one and a stored one
+        let fixed = [243, 72, 205, 201, 201, 215, 81, 200, 64, 162, 20, 1];
+        assert_eq!(inflate(&fixed).unwrap(), b"Hello, hello, hello!");
+        let dynamic = [5, 193, 193, 9, 0, 32, 16, 3, 193, 86, 182, 181, 67, 2, 249, 168, 15, 183, 127, 156, 177, 193, 6, 27, 108, 88, 35, 111, 228, 30, 108, 216, 227, 7];
+        assert_eq!(inflate(&dynamic).unwrap(), b"the the the the cat sat on the mat");
+        let stored = [1, 6, 0, 249, 255, 115, 116, 111, 114, 101, 100];
+        assert_eq!(inflate(&stored).unwrap(), b"stored");
+        
+        assert!(inflate(&fixed[..4]).is_err());
+    }
+    
+    #[test]
+    fn tsv_export_escapes_tabs_and_newlines() {
+        assert_eq!(tsv_field("a\tb\nc\\d"), "a\\tb\\nc\\\\d");
+        let entries = parse_srt(&srt_timed(&[(1000, 2000, "name\tvalue")])).unwrap();
+        let tsv = format_tsv_export(&entries);
+        assert!(tsv.ends_with("00:00:01,000 --> 00:00:02,000\tname\\tvalue\n"), "{}", tsv);
+        assert_eq!(tsv.lines().last().unwrap().matches('\t').count(), 1);
+    }
+    
+    #[test]
+    fn recursive_batch_continues_past_a_bad_folder() {
+        let dir = temp_dir("recursive");
+        for folder in ["a_good", "b_bad", "c_good"] {
+            fs::create_dir(dir.join(folder)).unwrap();
+            write_srt_files(&dir.join(folder), &["show [Chinese (Simplified)].srt"]);
         }
+        write_srt_files(&dir.join("b_bad"), &["show 2 [Chinese (Simplified)].srt"]);
+        
+        let error = run_cli(&["batch", "-d", dir.to_str().unwrap(), "-r", "--yes", "--on-duplicate", "error"]).unwrap_err();
+        assert_eq!(error.to_string(), "1 folder(s) failed");
+        assert!(dir.join("a_good/zh_srt.srt").exists());
+        assert!(!dir.join("b_bad/zh_srt.srt").exists());
+        assert!(dir.join("c_good/zh_srt.srt").exists());
     }
     
-    Ok(())
+    #[test]
+    fn batch_child_resolves_paths_from_our_working_directory() {
+        begin_run(quiet(false));
+        let input = Path::new("sub1/en_srt.srt");
+        let cmd = batch_process_command(input, Path::new("sub1/en_srt_ok.srt"), &Some(PathBuf::from("rules.txt")));
+        
+        assert_eq!(cmd.get_current_dir(), None);
+        let args: Vec<&std::ffi::OsStr> = cmd.get_args().collect();
+        assert_eq!(args[..6], ["process", "sub1/en_srt.srt", "-o", "sub1/en_srt_ok.srt", "-c", "rules.txt"]);
+    }
+    
+    #[test]
+    fn combine_equal_collapses_a_stutter() {
+        let mut entries = parse_srt(&srt_timed(&[(0, 500, "Wait"), (500, 900, "wait"), (900, 1500, "Wait!"), (2000, 3000, "go")])).unwrap();
+        let hits = apply_combine_equal(&mut entries, &["wait".to_string()], &mut Vec::new());
+        assert_eq!(texts(&entries), ["Wait", "Wait!", "go"]);
+        assert_eq!(entries[0].timestamp, "00:00:00,000 --> 00:00:00,900");
+        assert_eq!(hits, [1]);
+    }
+    
+    #[test]
+    fn turkish_locale_matches_dotted_and_dotless_i() {
+        let skip_words = ["IŞIK".to_string()];
+        let turkish = DefaultMatcher { locale: Some(CaseLocale::Tr) };
+        assert!(find_skip_word("ışık var", &skip_words, &turkish).is_some());
+        assert!(find_skip_word("ışık var", &skip_words, &DefaultMatcher::default()).is_none());
+        
+        assert!(find_skip_word("İstanbul", &["istanbul".to_string()], &turkish).is_some());
+        assert_eq!(locale_lowercase("DİKKAT IŞIK", Some(CaseLocale::Tr)), "dikkat ışık");
+    }
+    
+    #[test]
+    fn trailing_spaces_are_trimmed_unless_preserved() {
+        let input = srt_indexed(&[(1, "Hello   \nworld  ")]);
+        let trimmed = process_srt("trailing-trimmed", &input, "", &[]).unwrap();
+        assert!(trimmed.contains("\nHello\nworld\n") && !trimmed.contains(" \n"), "{:?}", trimmed);
+        
+        let kept = process_srt("trailing-kept", &input, "", &["--preserve-trailing-spaces"]).unwrap();
+        assert!(kept.contains("\nHello   \nworld  \n"), "{:?}", kept);
+    }
+    
+    #[test]
+    fn repair_turns_a_messy_file_into_clean_srt() {
+        let dir = temp_dir("repair");
+        let (input, output) = (dir.join("messy.srt"), dir.join("clean.srt"));
+        fs::write(&input, "\u{feff}7\r\n0:0:1.5->00:00:02:000  \r\nHi\r\n\r\n\r\n\r\n9\r\n00:00:03,000 -- > 00:00:04,000\r\nBye\r\n").unwrap();
+        
+        run_cli(&["repair", input.to_str().unwrap(), "-o", output.to_str().unwrap()]).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "1\n00:00:01,500 --> 00:00:02,000\nHi\n\n2\n00:00:03,000 --> 00:00:04,000\nBye\n");
+    }
+    
+    #[test]
+    fn combine_is_skipped_when_the_result_would_be_too_long() {
+        let input = srt_indexed(&[(1, "I want to thank"), (2, "you all very much indeed")]);
+        let phrases = [("thank".to_string(), "you".to_string())];
+        let combine = |max_chars| {
+            let mut entries = parse_srt(&input).unwrap();
+            apply_combine_rules(&mut entries, &phrases, &CombineOptions { max_chars, ..CombineOptions::default() }, &DefaultMatcher::default(), &mut Vec::new());
+            entries.len()
+        };
+        
+        assert_eq!(combine(Some(30)), 2);
+        assert_eq!(combine(Some(40)), 1);
+        assert_eq!(combine(None), 1);
+    }
+    
+    #[test]
+    fn quiet_silences_routine_messages_but_not_errors() {
+        let settings = |args: &[&str]| RunSettings::from_args(&Args::try_parse_from(["srt-handle"].iter().chain(args)).unwrap());
+        
+        begin_run(settings(&["-q", "doctor"]));
+        assert!(!log_enabled(LogLevel::Info));
+        print_error("Error: broken");
+        assert!(has_reported_problems());
+        
+        begin_run(settings(&["doctor"]));
+        assert!(log_enabled(LogLevel::Info) && !log_enabled(LogLevel::Debug));
+        begin_run(settings(&["-vv", "doctor"]));
+        assert!(log_enabled(LogLevel::Trace));
+    }
+    
+    #[test]
+    fn sample_config_parses_with_every_working_directive() {
+        begin_run(quiet(true));
+        let config = Config::from_content(SAMPLE_CONFIG).unwrap();
+        assert!(!has_reported_problems());
+        assert_eq!(config.skip_words.len(), 3);
+        assert_eq!(config.combine_phrases.len(), 3);
+        assert_eq!(config.combine_equal.len(), 2);
+        assert_eq!(config.end_words.len(), 6);
+        // Not applied anywhere, so the starter config must not enable them
+        assert!(config.insert_phrases.is_empty() && config.split_words.is_empty());
+    }
+    
+    #[test]
+    fn numbered_list_text_stays_in_its_cue() {
+        begin_run(quiet(false));
+        let input = "1\n00:00:01,000 --> 00:00:05,000\nCount down:\n\n3\n2\n1\n\n2\n00:00:06,000 --> 00:00:07,000\nLift off\n";
+        let entries = parse_srt(input).unwrap();
+        assert_eq!(texts(&entries), ["Count down:\n\n3\n2\n1", "Lift off"]);
+        assert_eq!(entries.iter().map(|e| e.index).collect::<Vec<_>>(), [1, 2]);
+    }
+    
+    #[test]
+    fn autotime_extends_into_the_gap_but_stops_before_the_next_cue() {
+        let mut entries = parse_srt(&srt_timed(&[(0, 300, "This needs more time to read"), (1000, 1300, "short"), (5000, 7000, "x")])).unwrap();
+        let (extended, too_fast) = apply_autotime(&mut entries, 17.0, 700, 0, &mut Vec::new());
+        
+        assert_eq!(timestamps(&entries), ["00:00:00,000 --> 00:00:01,000", "00:00:01,000 --> 00:00:01,700", "00:00:05,000 --> 00:00:07,000"]);
+        assert_eq!((extended, too_fast), (2, 1));
+        
+        let mut entries = parse_srt(&srt_timed(&[(0, 300, "This needs more time to read"), (1000, 1300, "short")])).unwrap();
+        apply_autotime(&mut entries, 17.0, 700, 80, &mut Vec::new());
+        assert_eq!(entries[0].timestamp, "00:00:00,000 --> 00:00:00,920");
+        
+        // CJK characters count double, as they do for --max-cps
+        let mut entries = parse_srt(&srt_timed(&[(0, 300, "一二三四五六七八九十")])).unwrap();
+        apply_autotime(&mut entries, 17.0, 700, 0, &mut Vec::new());
+        assert_eq!(entries[0].timestamp, "00:00:00,000 --> 00:00:01,177");
+    }
+    
+    #[test]
+    fn csv_import_reads_seconds_timestamps() {
+        let dir = temp_dir("csv-import");
+        let (input, output) = (dir.join("sheet.csv"), dir.join("out.srt"));
+        fs::write(&input, "start,end,text\n1.5,3,Hello\n4,5.25,\"Hi, there\"\n\"00:00:06,000\",7.5,Bye\n").unwrap();
+        
+        run_cli(&["import", input.to_str().unwrap(), "-o", output.to_str().unwrap()]).unwrap();
+        let entries = parse_srt(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(texts(&entries), ["Hello", "Hi, there", "Bye"]);
+        assert_eq!(timestamps(&entries), ["00:00:01,500 --> 00:00:03,000", "00:00:04,000 --> 00:00:05,250", "00:00:06,000 --> 00:00:07,500"]);
+        
+        let error = parse_csv_import("begin,end,text\n1,2,x\n").unwrap_err().to_string();
+        assert!(error.contains("start"), "{}", error);
+    }
+    
+    #[test]
+    fn combine_fires_on_tagged_text_only_when_tags_are_ignored() {
+        let input = srt_indexed(&[(1, "<i>I want to thank</i>"), (2, "you all")]);
+        let config = "COMBINE: \"thank you\"\n";
+        
+        let output = process_srt("match-tags", &input, config, &[]).unwrap();
+        assert_eq!(parse_srt(&output).unwrap().len(), 2);
+        let output = process_srt("match-ignore-tags", &input, config, &["--match-ignore-tags"]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["<i>I want to thank</i> you all"]);
+    }
+    
+    #[test]
+    fn combine_does_not_match_across_a_line_break() {
+        let phrases = [("thank".to_string(), "you".to_string())];
+        let combine = |cues: &[(u32, &str)]| {
+            let mut entries = parse_srt(&srt_indexed(cues)).unwrap();
+            apply_combine_rules(&mut entries, &phrases, &CombineOptions::default(), &DefaultMatcher::default(), &mut Vec::new());
+            entries.len()
+        };
+        
+        assert_eq!(combine(&[(1, "We thank\n我们感谢"), (2, "you\n你们")]), 2);
+        assert_eq!(combine(&[(1, "我们感谢\nWe thank"), (2, "你们\nyou")]), 2);
+        assert_eq!(combine(&[(1, "We thank"), (2, "you")]), 1);
+    }
+    
+    #[test]
+    fn output_template_expands_stem_and_extension() {
+        let input = Path::new("media/movie.srt");
+        assert_eq!(expand_output_template("{stem}.{lang}.processed.srt", input, "en", 1).unwrap(), Path::new("media/movie.en.processed.srt"));
+        assert_eq!(expand_output_template("{stem}_{index}.{ext}.bak", input, "", 3).unwrap(), Path::new("media/movie_3.srt.bak"));
+        assert!(expand_output_template("{name}.srt", input, "", 1).is_err());
+        
+        let (dir, mut cli) = process_args("output-template", &srt_indexed(&[(1, "Hello there")]), "");
+        cli.truncate(2);
+        cli.extend(["--output-template", "{stem}.{lang}.txt"].map(String::from));
+        run_cli(&cli.iter().map(String::as_str).collect::<Vec<_>>()).unwrap();
+        assert!(dir.join("in.en.txt").exists());
+    }
+    
+    #[test]
+    fn dedup_lines_collapses_a_repeated_line() {
+        assert_eq!(dedup_consecutive_lines("Hello\nHello\nworld"), "Hello\nworld");
+        assert_eq!(dedup_consecutive_lines("a\nb\na"), "a\nb\na");
+        
+        let output = process_srt("dedup-lines", &srt_indexed(&[(1, "Hello\nHello")]), "", &["--dedup-lines"]).unwrap();
+        assert_eq!(texts(&parse_srt(&output).unwrap()), ["Hello"]);
+    }
+    
+    #[test]
+    fn timestamps_stop_at_the_two_digit_hour_limit() {
+        assert_eq!(Timestamp::checked_from_millis(MAX_TIMESTAMP_MILLIS).unwrap().to_string(), "99:59:59,999");
+        let error = Timestamp::checked_from_millis(MAX_TIMESTAMP_MILLIS + 1).unwrap_err().to_string();
+        assert!(error.starts_with("100:00:00,000 is past 99:59:59,999"), "{}", error);
+        assert_eq!(Timestamp::from_millis(MAX_TIMESTAMP_MILLIS + 1).to_string(), "99:59:59,999");
+        assert!(Timestamp::parse("99:59:59,999").is_some());
+        assert!(Timestamp::parse("100:00:00,000").is_none());
+        
+        let mut entries = parse_srt("1\n99:59:58,000 --> 99:59:59,000\nlate\n").unwrap();
+        assert!(shift_timestamps(&mut entries, 999).is_ok());
+        assert!(shift_timestamps(&mut entries, 1).is_err());
+    }
+    
+    #[test]
+    fn split_by_speaker_writes_one_file_per_speaker() {
+        let dir = temp_dir("split-by-speaker");
+        let input = dir.join("talk.srt");
+        fs::write(&input, srt_indexed(&[(1, "JOHN: Hi"), (2, "MARY: Hello"), (3, "JOHN: Bye")])).unwrap();
+        
+        run_cli(&["split-by-speaker", input.to_str().unwrap()]).unwrap();
+        let john = parse_srt(&fs::read_to_string(dir.join("talk_john.srt")).unwrap()).unwrap();
+        assert_eq!(texts(&john), ["Hi", "Bye"]);
+        assert_eq!(john[1].timestamp, "00:00:02,000 --> 00:00:02,900");
+        let mary = parse_srt(&fs::read_to_string(dir.join("talk_mary.srt")).unwrap()).unwrap();
+        assert_eq!(texts(&mary), ["Hello"]);
+    }
+    
+    #[test]
+    fn renumber_step_two_from_one_gives_odd_indices() {
+        let entries = parse_srt(&srt_indexed(&[(1, "a"), (2, "b"), (3, "c")])).unwrap();
+        let output = format_srt_output(&entries, &OutputFormat { renumber_start: 1, renumber_step: 2, ..OutputFormat::default() });
+        assert_eq!(parse_srt(&output).unwrap().iter().map(|e| e.index).collect::<Vec<_>>(), [1, 3, 5]);
+    }
+    
+    #[test]
+    fn one_cue_far_faster_than_the_rest_is_flagged() {
+        let mut cues: Vec<(u64, u64, &str)> = (0..9).map(|i| (i * 2000, i * 2000 + 1000, "ten chars!")).collect();
+        cues.push((20_000, 20_500, "thirty characters in half a s"));
+        let report = format_cps_outliers(&parse_srt(&srt_timed(&cues)).unwrap());
+        
+        assert!(report.contains("CPS outliers: 1 above"), "{}", report);
+        assert!(report.lines().last().unwrap().trim_start().starts_with("10  00:00:20,000 --> 00:00:20,500  58.0"), "{}", report);
+        
+        let report = format_cps_outliers(&parse_srt(&srt_timed(&cues[..9])).unwrap());
+        assert!(report.contains("CPS outliers: none above"), "{}", report);
+    }
+    
+    #[test]
+    fn iterate_repeats_the_passes_until_nothing_changes() {
+        let input = srt_indexed(&[(1, "I thank you"), (2, "all")]);
+        let config = "COMBINE: \"thank you\"\nEND: \"you\"\n";
+        
+        let once = process_srt("iterate-once", &input, config, &[]).unwrap();
+        assert_eq!(texts(&parse_srt(&once).unwrap()), ["I thank", "you all"]);
+        let iterated = process_srt("iterate", &input, config, &["--iterate"]).unwrap();
+        assert_eq!(texts(&parse_srt(&iterated).unwrap()), ["I thank you all"]);
+    }
+    
+    fn end_rules(entries: &mut Vec<SrtEntry>, words: &[&str], redistribute_timing: bool) -> Vec<AuditRecord> {
+        let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+        let mut audit = Vec::new();
+        apply_end_rules(entries, &words, false, redistribute_timing, &DefaultMatcher::default(), &mut audit);
+        audit
+    }
+    
+    #[test]
+    fn redistribute_timing_moves_the_boundary_with_the_words() {
+        let input = "1\n00:00:01,000 --> 00:00:05,000\nI went to the store and\n\n2\n00:00:05,500 --> 00:00:07,000\nbought milk\n";
+        
+        let mut entries = parse_srt(input).unwrap();
+        end_rules(&mut entries, &["and"], false);
+        assert_eq!(entries[0].timestamp, "00:00:01,000 --> 00:00:05,000");
+        
+        let mut entries = parse_srt(input).unwrap();
+        let audit = end_rules(&mut entries, &["and"], true);
+        // "and" is 3 of 18 characters, so the boundary moves back 3/18 of 4s
+        assert_eq!(texts(&entries), ["I went to the store", "and bought milk"]);
+        assert_eq!(entries[0].timestamp, "00:00:01,000 --> 00:00:04,333");
+        assert_eq!(entries[1].timestamp, "00:00:04,833 --> 00:00:07,000");
+        assert_eq!(audit[0].detail, "moved \"and\" to 2, boundary 667ms earlier");
+    }
+    
+    #[test]
+    fn end_move_that_empties_a_cue_drops_it() {
+        let input = "1\n00:00:01,000 --> 00:00:02,000\nand\n\n2\n00:00:02,500 --> 00:00:04,000\nbought milk\n";
+        
+        for (redistribute, start) in [(false, "00:00:02,500"), (true, "00:00:01,000")] {
+            let mut entries = parse_srt(input).unwrap();
+            end_rules(&mut entries, &["and"], redistribute);
+            assert_eq!(texts(&entries), ["and bought milk"]);
+            assert_eq!(entries[0].timestamp, format!("{} --> 00:00:04,000", start));
+            
+            let output = format_srt_output(&entries, &OutputFormat { renumber_start: 1, ..OutputFormat::default() });
+            assert_eq!(parse_srt(&output).unwrap().len(), 1);
+        }
+    }
 }